@@ -0,0 +1,251 @@
+//! Keeps the audio of ingested songs, so they can be fingerprinted again
+//! after the algorithm changes without downloading anything.
+//!
+//! The store holds the signal the fingerprinter analyses: mono, low-passed
+//! and downsampled (about 11 kHz for CD audio), as uncompressed 16-bit WAV,
+//! which is about 1.3 MB a minute. Files are named after the SHA-256 of their
+//! content, so the same audio is only kept once, and removed once no song
+//! uses it anymore.
+
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use main_app::utils::AudioBuffer;
+use sha2::{Digest, Sha256};
+
+use crate::settings::AudioStoreSettings;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// Storing the file would take the store past its quota.
+    QuotaExceeded { used: u64, needed: u64, max: u64 },
+    NotFound(String),
+    Io(io::Error),
+    Wav(hound::Error),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::QuotaExceeded { used, needed, max } => write!(
+                f,
+                "the audio store is full ({} of {} bytes used, {} more needed)",
+                used, max, needed
+            ),
+            StoreError::NotFound(hash) => write!(f, "no stored audio {}", hash),
+            StoreError::Io(err) => write!(f, "IO error: {}", err),
+            StoreError::Wav(err) => write!(f, "invalid WAV: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<hound::Error> for StoreError {
+    fn from(err: hound::Error) -> Self {
+        StoreError::Wav(err)
+    }
+}
+
+/// Bytes used by each store directory, counted the first time a file is put
+/// there and kept up to date from then on. Puts hold the lock from the quota
+/// check until their file is in place, so ingest workers storing at the same
+/// time can't overshoot the quota together.
+static USAGE: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+
+pub struct AudioStore {
+    directory: PathBuf,
+    max_bytes: u64,
+}
+
+impl AudioStore {
+    pub fn new(directory: impl Into<PathBuf>, max_size_mb: u64) -> AudioStore {
+        AudioStore {
+            directory: directory.into(),
+            max_bytes: max_size_mb * 1024 * 1024,
+        }
+    }
+
+    /// The configured store, or `None` when keeping audio is turned off.
+    pub fn from_settings(settings: &AudioStoreSettings) -> Option<AudioStore> {
+        settings
+            .enabled
+            .then(|| AudioStore::new(&settings.directory, settings.max_size_mb))
+    }
+
+    /// Files are spread over subdirectories named after the first two hex
+    /// digits of their hash.
+    pub fn path_of(&self, hash: &str) -> PathBuf {
+        self.directory.join(&hash[..2.min(hash.len())]).join(format!("{}.wav", hash))
+    }
+
+    /// Saves a signal and returns its hash. Audio that is already stored is
+    /// not written (or counted against the quota) again.
+    pub fn put(&self, signal: &AudioBuffer<f64>) -> Result<String, StoreError> {
+        let wav = encode_wav(signal)?;
+        let hash = format!("{:x}", Sha256::digest(&wav));
+        let path = self.path_of(&hash);
+
+        let mut usage = USAGE.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+        if path.exists() {
+            return Ok(hash);
+        }
+        let used = match usage.get(&self.directory) {
+            Some(used) => *used,
+            None => self.used_bytes()?,
+        };
+        let needed = wav.len() as u64;
+        if used + needed > self.max_bytes {
+            return Err(StoreError::QuotaExceeded { used, needed, max: self.max_bytes });
+        }
+
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        // written under another name first so a crash never leaves half a file
+        // behind the final one
+        let partial = path.with_extension("wav.part");
+        std::fs::write(&partial, &wav)?;
+        std::fs::rename(&partial, &path)?;
+        usage.insert(self.directory.clone(), used + needed);
+        Ok(hash)
+    }
+
+    /// Reads a stored signal back.
+    pub fn get(&self, hash: &str) -> Result<AudioBuffer<f64>, StoreError> {
+        let path = self.path_of(hash);
+        let mut reader = match hound::WavReader::open(&path) {
+            Ok(reader) => reader,
+            Err(hound::Error::IoError(err)) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(StoreError::NotFound(hash.to_string()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let sample_rate = reader.spec().sample_rate;
+        let signal = reader
+            .samples::<i16>()
+            .map(|sample| sample.map(|value| value as f64 / i16::MAX as f64))
+            .collect::<Result<Vec<f64>, _>>()?;
+        Ok(AudioBuffer::new(signal, sample_rate))
+    }
+
+    /// Removes a stored file and gives its size back to the quota. Audio that
+    /// isn't stored is left alone.
+    pub fn remove(&self, hash: &str) -> Result<(), StoreError> {
+        let path = self.path_of(hash);
+        let mut usage = USAGE.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        std::fs::remove_file(&path)?;
+        if let Some(used) = usage.get_mut(&self.directory) {
+            *used = used.saturating_sub(size);
+        }
+        Ok(())
+    }
+
+    /// Size of all stored files in bytes, walking the whole store.
+    pub fn used_bytes(&self) -> io::Result<u64> {
+        directory_size(&self.directory)
+    }
+}
+
+fn encode_wav(signal: &AudioBuffer<f64>) -> Result<Vec<u8>, hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: signal.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for &value in &signal.samples {
+        writer.write_sample((value.clamp(-1.0, 1.0) * i16::MAX as f64).round() as i16)?;
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+fn directory_size(directory: &Path) -> io::Result<u64> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_audio_round_trips_and_respects_the_quota() {
+        let directory = std::env::temp_dir().join(format!("main_app_audio_store_{}", std::process::id()));
+        let store = AudioStore::new(&directory, 1);
+        let signal = AudioBuffer::new((0..11025).map(|i| (i as f64 * 0.05).sin() * 0.5).collect(), 11025);
+
+        let hash = store.put(&signal).unwrap();
+        assert!(store.path_of(&hash).is_file());
+        assert_eq!(store.put(&signal).unwrap(), hash);
+
+        let stored = store.get(&hash).unwrap();
+        assert_eq!(stored.sample_rate, 11025);
+        assert_eq!(stored.len(), signal.len());
+        assert!(stored.samples.iter().zip(&signal.samples).all(|(a, b)| (a - b).abs() < 1e-4));
+
+        // a minute of audio doesn't fit in what is left of one megabyte
+        let long = AudioBuffer::new((0..11025 * 60).map(|i| (i as f64 * 0.01).sin() * 0.5).collect(), 11025);
+        assert!(matches!(store.put(&long), Err(StoreError::QuotaExceeded { .. })));
+        assert!(matches!(store.get("00ff"), Err(StoreError::NotFound(_))));
+
+        store.remove(&hash).unwrap();
+        assert!(!store.path_of(&hash).exists());
+        assert!(matches!(store.get(&hash), Err(StoreError::NotFound(_))));
+        store.remove(&hash).unwrap();
+        assert_eq!(store.used_bytes().unwrap(), 0);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn concurrent_puts_stay_within_the_quota() {
+        let directory = std::env::temp_dir().join(format!("main_app_audio_store_concurrent_{}", std::process::id()));
+        // ten seconds of audio is about 215 KiB, so four of them fit in one megabyte
+        let stored: Vec<bool> = std::thread::scope(|scope| {
+            let puts: Vec<_> = (0..8)
+                .map(|i| {
+                    let directory = &directory;
+                    scope.spawn(move || {
+                        let signal = AudioBuffer::new((0..11025 * 10).map(|j| (j as f64 * 0.01 * (i + 1) as f64).sin() * 0.5).collect(), 11025);
+                        AudioStore::new(directory, 1).put(&signal).is_ok()
+                    })
+                })
+                .collect();
+            puts.into_iter().map(|put| put.join().unwrap()).collect()
+        });
+
+        assert_eq!(stored.iter().filter(|stored| **stored).count(), 4);
+        assert!(AudioStore::new(&directory, 1).used_bytes().unwrap() <= 1024 * 1024);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
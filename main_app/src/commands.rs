@@ -0,0 +1,641 @@
+//! Maintenance subcommands registered next to cot's own CLI commands.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use cot::cli::CliTask;
+use cot::cli::clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use cot::db::{query, Auto, Database};
+use cot::db::Model;
+use cot::db::migrations::{MigrationEngine, SyncDynMigration};
+use cot::project::{Bootstrapper, WithConfig};
+use futures_util::StreamExt;
+use main_app::utils::{AudioBuffer, DecodeReport};
+use serde::Serialize;
+
+use crate::audio_store::AudioStore;
+use crate::ingest::{self, IngestError};
+use crate::models::{SearchLog, Song};
+use crate::shazam::Couple;
+
+/// Boots the project up to the database and applies pending migrations,
+/// the same way the server does on start.
+async fn task_database(bootstrapper: Bootstrapper<WithConfig>) -> cot::Result<Arc<Database>> {
+    let bootstrapper = bootstrapper.with_apps().with_database().await?;
+    let context = bootstrapper.context();
+
+    let mut migrations: Vec<Box<SyncDynMigration>> = Vec::new();
+    for app in context.apps() {
+        migrations.extend(app.migrations());
+    }
+    let database = context.database().clone();
+    MigrationEngine::new(migrations)?.run(&database).await?;
+    crate::models::create_indexes(&database).await?;
+    Ok(database)
+}
+
+/// Replays the query samples kept by the query store against the current
+/// index and reports where the top match differs from the logged one.
+pub struct ReplayQueries;
+
+#[derive(Default)]
+struct ReplaySummary {
+    replayed: usize,
+    unchanged: usize,
+    newly_matched: usize,
+    lost: usize,
+    changed: usize,
+    missing: usize,
+    failed: usize,
+    logged_hashes: u64,
+    replayed_hashes: u64,
+    logged_ms: u64,
+    replayed_ms: u64,
+}
+
+#[async_trait(?Send)]
+impl CliTask for ReplayQueries {
+    fn subcommand(&self) -> Command {
+        Command::new("replay-queries")
+            .about("Replays stored search samples against the current index to find regressions")
+            .arg(
+                Arg::new("limit")
+                    .help("Only replay the most recent N stored queries")
+                    .long("limit")
+                    .value_parser(value_parser!(usize)),
+            )
+    }
+
+    async fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        bootstrapper: Bootstrapper<WithConfig>,
+    ) -> cot::Result<()> {
+        let db = task_database(bootstrapper).await?;
+
+        let mut logs: Vec<SearchLog> = SearchLog::objects()
+            .all(&db)
+            .await?
+            .into_iter()
+            .filter(|log| log.sample_path.is_some())
+            .collect();
+        // the database returns rows in no particular order
+        logs.sort_by_key(|log| log.id.unwrap());
+        if let Some(limit) = matches.get_one::<usize>("limit") {
+            let skip = logs.len().saturating_sub(*limit);
+            logs.drain(..skip);
+        }
+        println!("Replaying {} stored queries...", logs.len());
+
+        let mut summary = ReplaySummary::default();
+        for log in logs {
+            let sample_path = log.sample_path.clone().unwrap_or_default();
+            if !Path::new(&sample_path).exists() {
+                summary.missing += 1;
+                continue;
+            }
+
+            let audio = match main_app::utils::decode_audio_file(&sample_path) {
+                Ok(audio) => audio,
+                Err(err) => {
+                    eprintln!("#{}: failed to decode {}: {}", log.id, sample_path, err);
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+            let (found_songs, hash_count, query_duration) = match crate::shazam::find_matches(&db, &audio).await {
+                Ok(result) => result,
+                Err(err) => {
+                    eprintln!("#{}: matching failed: {}", log.id, err);
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+
+            summary.replayed += 1;
+            summary.logged_hashes += log.hash_count as u64;
+            summary.replayed_hashes += hash_count as u64;
+            summary.logged_ms += log.query_duration_ms as u64;
+            summary.replayed_ms += query_duration.as_millis() as u64;
+
+            let top_song_id = found_songs.first().map(|mtch| mtch.song_id);
+            match (log.chosen_song_id, top_song_id) {
+                (before, after) if before == after => summary.unchanged += 1,
+                (None, Some(after)) => {
+                    println!("#{}: now matches song #{}", log.id, after);
+                    summary.newly_matched += 1;
+                }
+                (Some(before), None) => {
+                    println!("#{}: REGRESSION, matched song #{} before, nothing now", log.id, before);
+                    summary.lost += 1;
+                }
+                (before, after) => {
+                    println!("#{}: top match changed from {:?} to {:?}", log.id, before, after);
+                    summary.changed += 1;
+                }
+            }
+        }
+
+        print_summary(&summary);
+        Ok(())
+    }
+}
+
+fn print_summary(summary: &ReplaySummary) {
+    let average = |total: u64| {
+        if summary.replayed == 0 { 0.0 } else { total as f64 / summary.replayed as f64 }
+    };
+
+    println!();
+    println!("replayed:        {}", summary.replayed);
+    println!("unchanged:       {}", summary.unchanged);
+    println!("newly matched:   {}", summary.newly_matched);
+    println!("lost match:      {}", summary.lost);
+    println!("changed match:   {}", summary.changed);
+    println!("missing samples: {}", summary.missing);
+    println!("failed:          {}", summary.failed);
+    println!(
+        "avg hashes:      {:.1} logged, {:.1} now",
+        average(summary.logged_hashes),
+        average(summary.replayed_hashes)
+    );
+    println!(
+        "avg query time:  {:.1}ms logged, {:.1}ms now",
+        average(summary.logged_ms),
+        average(summary.replayed_ms)
+    );
+}
+
+/// Seeds the catalogue from a music library: every supported file below a
+/// directory is decoded and fingerprinted in parallel and stored as a local
+/// file song. Files whose content was ingested before are skipped.
+pub struct IngestDirectory;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FileStatus {
+    Ingested,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    path: String,
+    status: FileStatus,
+    song_id: Option<i64>,
+    /// Why the file was skipped or failed.
+    reason: Option<String>,
+    hash_count: usize,
+    /// Corrupt packets the decoder left out of the file.
+    packets_dropped: u64,
+    decode_ms: u64,
+    fingerprint_ms: u64,
+    store_ms: u64,
+}
+
+impl FileReport {
+    fn new(path: &Path, status: FileStatus, reason: Option<String>) -> FileReport {
+        FileReport {
+            path: path.display().to_string(),
+            status,
+            song_id: None,
+            reason,
+            hash_count: 0,
+            packets_dropped: 0,
+            decode_ms: 0,
+            fingerprint_ms: 0,
+            store_ms: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IngestReport {
+    directory: String,
+    files: usize,
+    ingested: usize,
+    skipped: usize,
+    failed: usize,
+    total_ms: u64,
+    decode_ms: u64,
+    fingerprint_ms: u64,
+    store_ms: u64,
+    reports: Vec<FileReport>,
+}
+
+/// The songs that were ingested from files.
+#[derive(Default)]
+struct KnownFiles {
+    paths: HashMap<String, i64>,
+    /// Song ids with the path each song was ingested from.
+    hashes: HashMap<String, (i64, Option<String>)>,
+}
+
+impl KnownFiles {
+    fn new(songs: Vec<Song>) -> KnownFiles {
+        let mut known = KnownFiles::default();
+        for song in songs {
+            let song_id = song.id.unwrap();
+            if let Some(file_path) = &song.file_path {
+                known.paths.insert(file_path.clone(), song_id);
+            }
+            if let Some(content_hash) = song.content_hash {
+                known.hashes.insert(content_hash, (song_id, song.file_path));
+            }
+        }
+        known
+    }
+}
+
+/// The part of ingesting a file that runs on the blocking thread pool.
+enum PreparedFile {
+    /// The path, or the content hash of a copy that is still in place,
+    /// belongs to a stored song.
+    Known { song_id: i64 },
+    /// The content hash belongs to a stored song whose file is gone.
+    Moved { song_id: i64 },
+    Fingerprinted {
+        content_hash: String,
+        fingerprints: HashMap<u32, Couple>,
+        /// What the audio store keeps, see [`ingest::fingerprint_analysis_audio`].
+        signal: AudioBuffer<f64>,
+        decode_report: DecodeReport,
+        decode_time: Duration,
+        fingerprint_time: Duration,
+    },
+    Failed(String),
+}
+
+fn prepare_file(path: &Path, known: &KnownFiles) -> PreparedFile {
+    // only files at new paths are read, so a second run over a library is quick
+    if let Some(song_id) = known.paths.get(&path.display().to_string()) {
+        return PreparedFile::Known { song_id: *song_id };
+    }
+    let content_hash = match ingest::content_hash(path) {
+        Ok(content_hash) => content_hash,
+        Err(err) => return PreparedFile::Failed(format!("could not read the file: {}", err)),
+    };
+    if let Some((song_id, file_path)) = known.hashes.get(&content_hash) {
+        let song_id = *song_id;
+        return match file_path {
+            Some(file_path) if Path::new(file_path).exists() => PreparedFile::Known { song_id },
+            _ => PreparedFile::Moved { song_id },
+        };
+    }
+
+    // decoding includes filtering and downsampling, which happen on the way
+    let start = Instant::now();
+    let (signal, decode_report) = match ingest::analysis_audio_of_file(path, None) {
+        Ok(decoded) => decoded,
+        Err(err) => return PreparedFile::Failed(err.to_string()),
+    };
+    let decode_time = start.elapsed();
+
+    let start = Instant::now();
+    let fingerprints = match ingest::fingerprint_analysis_audio(&signal) {
+        Ok(fingerprints) => fingerprints,
+        Err(err) => return PreparedFile::Failed(IngestError::Fingerprint(err).to_string()),
+    };
+    PreparedFile::Fingerprinted {
+        content_hash,
+        fingerprints,
+        signal,
+        decode_report,
+        decode_time,
+        fingerprint_time: start.elapsed(),
+    }
+}
+
+/// Every supported audio file below `directory`, in a stable order.
+fn find_audio_files(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                directories.push(path);
+            } else if ingest::is_supported_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[async_trait(?Send)]
+impl CliTask for IngestDirectory {
+    fn subcommand(&self) -> Command {
+        Command::new("ingest-dir")
+            .about("Ingests every audio file below a directory into the catalogue")
+            .arg(
+                Arg::new("directory")
+                    .help("Directory to walk for audio files (MP3, FLAC, WAV, OGG, M4A, AAC, WebM, Opus)")
+                    .required(true)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                Arg::new("jobs")
+                    .help("Files decoded and fingerprinted at the same time [default: number of CPUs]")
+                    .long("jobs")
+                    .short('j')
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                Arg::new("report")
+                    .help("Write a JSON report of every file to this path")
+                    .long("report")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+    }
+
+    async fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        bootstrapper: Bootstrapper<WithConfig>,
+    ) -> cot::Result<()> {
+        let directory = matches.get_one::<PathBuf>("directory").unwrap();
+        let jobs = matches
+            .get_one::<usize>("jobs")
+            .copied()
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cpus| cpus.get()))
+            .max(1);
+        let db = task_database(bootstrapper).await?;
+
+        let files = find_audio_files(directory).map_err(|err| {
+            cot::Error::internal(format!("could not read `{}`: {}", directory.display(), err))
+        })?;
+        let known = Arc::new(KnownFiles::new(Song::objects().all(&db).await?));
+        println!("Ingesting {} files from {} with {} jobs...", files.len(), directory.display(), jobs);
+
+        let start = Instant::now();
+        let mut prepared = futures_util::stream::iter(files.clone())
+            .map(|path| {
+                let known = known.clone();
+                tokio::task::spawn_blocking(move || {
+                    let prepared = prepare_file(&path, &known);
+                    (path, prepared)
+                })
+            })
+            .buffer_unordered(jobs);
+
+        // the same file can appear twice below the directory
+        let mut ingested_hashes: HashMap<String, i64> = HashMap::new();
+        let mut reports = Vec::with_capacity(files.len());
+        while let Some(result) = prepared.next().await {
+            let (path, prepared) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    eprintln!("a worker crashed: {}", err);
+                    continue;
+                }
+            };
+            let report = store_prepared_file(&db, &path, prepared, &mut ingested_hashes).await;
+            match report.status {
+                FileStatus::Ingested => println!("ingested {} as song #{}", report.path, report.song_id.unwrap_or(0)),
+                FileStatus::Skipped => println!("skipped {}: {}", report.path, report.reason.as_deref().unwrap_or("")),
+                FileStatus::Failed => println!("FAILED {}: {}", report.path, report.reason.as_deref().unwrap_or("")),
+            }
+            if report.packets_dropped > 0 {
+                println!("  {} corrupt packets dropped from {}", report.packets_dropped, report.path);
+            }
+            reports.push(report);
+        }
+
+        // files that never came back from a crashed worker
+        let reported: HashSet<String> = reports.iter().map(|report| report.path.clone()).collect();
+        for path in &files {
+            if !reported.contains(&path.display().to_string()) {
+                reports.push(FileReport::new(path, FileStatus::Failed, Some("the worker crashed".to_string())));
+            }
+        }
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let count = |status: fn(&FileStatus) -> bool| reports.iter().filter(|report| status(&report.status)).count();
+        let report = IngestReport {
+            directory: directory.display().to_string(),
+            files: files.len(),
+            ingested: count(|status| matches!(status, FileStatus::Ingested)),
+            skipped: count(|status| matches!(status, FileStatus::Skipped)),
+            failed: count(|status| matches!(status, FileStatus::Failed)),
+            total_ms: start.elapsed().as_millis() as u64,
+            decode_ms: reports.iter().map(|report| report.decode_ms).sum(),
+            fingerprint_ms: reports.iter().map(|report| report.fingerprint_ms).sum(),
+            store_ms: reports.iter().map(|report| report.store_ms).sum(),
+            reports,
+        };
+        print_ingest_report(&report);
+
+        if let Some(report_path) = matches.get_one::<PathBuf>("report") {
+            let json = serde_json::to_string_pretty(&report).map_err(cot::Error::internal)?;
+            std::fs::write(report_path, json).map_err(|err| {
+                cot::Error::internal(format!("could not write `{}`: {}", report_path.display(), err))
+            })?;
+            println!("report written to {}", report_path.display());
+        }
+        Ok(())
+    }
+}
+
+async fn store_prepared_file(
+    db: &Arc<Database>,
+    path: &Path,
+    prepared: PreparedFile,
+    ingested_hashes: &mut HashMap<String, i64>,
+) -> FileReport {
+    let (content_hash, fingerprints, signal, decode_report, decode_time, fingerprint_time) = match prepared {
+        PreparedFile::Known { song_id } => {
+            return FileReport::new(path, FileStatus::Skipped, Some(format!("already ingested as song #{}", song_id)));
+        }
+        PreparedFile::Moved { song_id } => return move_library_song(db, path, song_id).await,
+        PreparedFile::Failed(reason) => return FileReport::new(path, FileStatus::Failed, Some(reason)),
+        PreparedFile::Fingerprinted {
+            content_hash,
+            fingerprints,
+            signal,
+            decode_report,
+            decode_time,
+            fingerprint_time,
+        } => (content_hash, fingerprints, signal, decode_report, decode_time, fingerprint_time),
+    };
+    if let Some(song_id) = ingested_hashes.get(&content_hash) {
+        return FileReport::new(path, FileStatus::Skipped, Some(format!("same file as song #{}", song_id)));
+    }
+
+    let mut report = FileReport::new(path, FileStatus::Failed, None);
+    report.hash_count = fingerprints.len();
+    report.packets_dropped = decode_report.packets_dropped;
+    report.decode_ms = decode_time.as_millis() as u64;
+    report.fingerprint_ms = fingerprint_time.as_millis() as u64;
+
+    let start = Instant::now();
+    match store_library_song(db, path, content_hash.clone(), &fingerprints, &signal).await {
+        Ok(song_id) => {
+            ingested_hashes.insert(content_hash, song_id);
+            report.status = FileStatus::Ingested;
+            report.song_id = Some(song_id);
+        }
+        Err(IngestError::Duplicate(duplicate)) => {
+            report.status = FileStatus::Skipped;
+            report.reason = Some(IngestError::Duplicate(duplicate).to_string());
+        }
+        Err(err) => report.reason = Some(err.to_string()),
+    }
+    report.store_ms = start.elapsed().as_millis() as u64;
+    report
+}
+
+/// Points a song at the new path of its file.
+async fn move_library_song(db: &Arc<Database>, path: &Path, song_id: i64) -> FileReport {
+    let moved = async {
+        let Some(mut song) = query!(Song, $id == Auto::from(song_id)).get(db).await? else {
+            return Ok(None);
+        };
+        let from = song.file_path.replace(path.display().to_string());
+        song.update(db).await?;
+        Ok::<_, cot::db::DatabaseError>(Some(from))
+    };
+    match moved.await {
+        Ok(Some(from)) => FileReport::new(
+            path,
+            FileStatus::Skipped,
+            Some(format!(
+                "already ingested as song #{}, moved from {}",
+                song_id,
+                from.as_deref().unwrap_or("an unknown path")
+            )),
+        ),
+        Ok(None) => FileReport::new(path, FileStatus::Failed, Some(format!("song #{} was removed while ingesting", song_id))),
+        Err(err) => FileReport::new(path, FileStatus::Failed, Some(format!("could not update song #{}: {}", song_id, err))),
+    }
+}
+
+async fn store_library_song(
+    db: &Arc<Database>,
+    path: &Path,
+    content_hash: String,
+    fingerprints: &HashMap<u32, Couple>,
+    signal: &AudioBuffer<f64>,
+) -> Result<i64, IngestError> {
+    if let Some(duplicate) = crate::shazam::find_duplicate(fingerprints, db)
+        .await
+        .map_err(IngestError::DuplicateCheck)?
+    {
+        return Err(IngestError::Duplicate(duplicate));
+    }
+
+    let file_name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    let mut song = Song::new_local_file(&file_name);
+    song.file_path = Some(path.display().to_string());
+    song.content_hash = Some(content_hash);
+    song.audio_hash = ingest::keep_audio(signal);
+    song.save(db).await?;
+
+    let song_id = song.id.unwrap();
+    let fingerprints: Vec<(u32, Couple)> = fingerprints.iter().map(|(address, couple)| (*address, *couple)).collect();
+    if let Err(err) = ingest::store_fingerprints(db, song_id, &fingerprints).await {
+        // a partly stored song would be skipped as known on the next run
+        if let Err(err) = ingest::delete_song(db, song_id).await {
+            eprintln!("failed to remove the partial song #{}: {}", song_id, err);
+        }
+        return Err(err.into());
+    }
+    Ok(song_id)
+}
+
+fn print_ingest_report(report: &IngestReport) {
+    println!();
+    println!("files:           {}", report.files);
+    println!("ingested:        {}", report.ingested);
+    println!("skipped:         {}", report.skipped);
+    println!("failed:          {}", report.failed);
+    println!("total time:      {:.1}s", report.total_ms as f64 / 1000.0);
+    println!(
+        "time spent:      {:.1}s decoding, {:.1}s fingerprinting, {:.1}s storing",
+        report.decode_ms as f64 / 1000.0,
+        report.fingerprint_ms as f64 / 1000.0,
+        report.store_ms as f64 / 1000.0
+    );
+}
+
+/// Fingerprints songs again from the audio kept in the audio store, so a
+/// changed fingerprinting algorithm can be applied without downloading
+/// anything.
+pub struct Refingerprint;
+
+#[async_trait(?Send)]
+impl CliTask for Refingerprint {
+    fn subcommand(&self) -> Command {
+        Command::new("refingerprint")
+            .about("Replaces the fingerprints of songs with ones computed from the audio store")
+            .arg(
+                Arg::new("song")
+                    .help("Only re-fingerprint this song (can be repeated)")
+                    .long("song")
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(i64)),
+            )
+    }
+
+    async fn execute(
+        &mut self,
+        matches: &ArgMatches,
+        bootstrapper: Bootstrapper<WithConfig>,
+    ) -> cot::Result<()> {
+        let db = task_database(bootstrapper).await?;
+        let store = AudioStore::from_settings(&crate::settings::get().audio_store)
+            .ok_or_else(|| cot::Error::internal("the audio store is not enabled in the config"))?;
+
+        let only: Option<HashSet<i64>> = matches.get_many::<i64>("song").map(|ids| ids.copied().collect());
+        let mut songs: Vec<Song> = Song::objects()
+            .all(&db)
+            .await?
+            .into_iter()
+            .filter(|song| only.as_ref().is_none_or(|only| only.contains(&song.id.unwrap())))
+            .collect();
+        songs.sort_by_key(|song| song.id.unwrap());
+        println!("Re-fingerprinting {} songs...", songs.len());
+
+        let (mut done, mut missing, mut failed) = (0, 0, 0);
+        for song in songs {
+            let song_id = song.id.unwrap();
+            let Some(audio_hash) = song.audio_hash.clone() else {
+                println!("#{}: no stored audio for {}", song_id, song.title());
+                missing += 1;
+                continue;
+            };
+
+            let fingerprints = store
+                .get(&audio_hash)
+                .map_err(|err| err.to_string())
+                .and_then(|signal| ingest::fingerprint_analysis_audio(&signal).map_err(|err| err.to_string()));
+            let fingerprints: Vec<(u32, Couple)> = match fingerprints {
+                Ok(fingerprints) => fingerprints.into_iter().collect(),
+                Err(err) => {
+                    println!("#{}: FAILED {}: {}", song_id, song.title(), err);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            if let Err(err) = ingest::replace_fingerprints(&db, song_id, &fingerprints).await {
+                println!("#{}: FAILED {}, kept its old fingerprints: {}", song_id, song.title(), err);
+                failed += 1;
+                continue;
+            }
+            println!("#{}: {} fingerprints for {}", song_id, fingerprints.len(), song.title());
+            done += 1;
+        }
+
+        println!();
+        println!("re-fingerprinted: {}", done);
+        println!("no stored audio:  {}", missing);
+        println!("failed:           {}", failed);
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Dumps of the signals the fingerprinter works on, as WAV files, to listen to
+//! what it hears while tuning the pipeline: the decoded audio, the same
+//! after the low-pass filter, the downsampled analysis signal and the
+//! sections preprocessing keeps of it. Configured in `[main_app.debug]`.
+//!
+//! Failing to write a dump is logged and otherwise ignored; it never fails
+//! the ingestion or search it was taken from.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use main_app::utils::{write_wav, AudioBuffer, WavStream};
+use symphonia::core::conv::IntoSample;
+
+use crate::shazam::preprocess::preprocess;
+use crate::shazam::spectogram::AnalysisSignal;
+
+/// Where the stages of one ingest job or search are written, as
+/// `<name>-<stage>.wav`.
+pub struct AudioDump {
+    directory: PathBuf,
+    name: String,
+}
+
+impl AudioDump {
+    pub fn new(directory: impl Into<PathBuf>, name: impl Into<String>) -> AudioDump {
+        AudioDump { directory: directory.into(), name: name.into() }
+    }
+
+    /// The dump of an ingest job, if the settings ask for one.
+    pub fn for_job(job_id: i64) -> Option<AudioDump> {
+        let settings = &crate::settings::get().debug;
+        let directory = settings.dump_dir.as_ref()?;
+        if settings.dump_jobs.as_ref().is_some_and(|jobs| !jobs.contains(&job_id)) {
+            return None;
+        }
+        Some(AudioDump::new(directory, format!("job-{}", job_id)))
+    }
+
+    /// The dump of a search, named after its search log, if the settings ask
+    /// for one.
+    pub fn for_search(search_log_id: i64) -> Option<AudioDump> {
+        let settings = &crate::settings::get().debug;
+        let directory = settings.dump_dir.as_ref().filter(|_| settings.dump_searches)?;
+        Some(AudioDump::new(directory, format!("search-{}", search_log_id)))
+    }
+
+    pub fn path(&self, stage: &str) -> PathBuf {
+        self.directory.join(format!("{}-{}.wav", self.name, stage))
+    }
+
+    /// A stage that is written as it is computed.
+    pub fn stream(&self, stage: &str, sample_rate: u32) -> DumpStream {
+        let path = self.path(stage);
+        let wav = std::fs::create_dir_all(&self.directory)
+            .map_err(hound::Error::from)
+            .and_then(|()| WavStream::create(&path, sample_rate));
+        let wav = match wav {
+            Ok(wav) => Some(wav),
+            Err(err) => {
+                log_failure(&path, err);
+                None
+            }
+        };
+        DumpStream { path, wav }
+    }
+
+    pub fn write<S: IntoSample<f32> + Copy>(&self, stage: &str, samples: &[S], sample_rate: u32) {
+        let path = self.path(stage);
+        let written = std::fs::create_dir_all(&self.directory)
+            .map_err(hound::Error::from)
+            .and_then(|()| write_wav(&path, samples, sample_rate));
+        if let Err(err) = written {
+            log_failure(&path, err);
+        }
+    }
+
+    /// Writes the analysis signal and every section preprocessing keeps of
+    /// it, each named after where it starts.
+    pub fn write_analysis(&self, signal: &AudioBuffer<f64>) {
+        let analysis_rate = signal.sample_rate;
+        self.write("downsampled", &signal.samples, analysis_rate);
+        let options = crate::settings::get().preprocess.options();
+        for section in preprocess(&signal.samples, analysis_rate as usize, &options).sections {
+            let start_ms = (section.offset_secs(analysis_rate as usize) * 1000.0).round();
+            self.write(&format!("section-{}ms", start_ms), &section.signal, analysis_rate);
+        }
+    }
+
+    /// Writes every stage of a search sample, which is short enough to go
+    /// through the pipeline a second time.
+    pub fn write_query(&self, audio: &AudioBuffer) {
+        self.write("decoded", &audio.samples, audio.sample_rate);
+        let Ok(mut signal) = AnalysisSignal::new(audio.sample_rate) else {
+            return;
+        };
+        let mut filtered = Vec::with_capacity(audio.len());
+        signal.push_inspect(audio.iter_as(), |value| filtered.push(value));
+        self.write("filtered", &filtered, audio.sample_rate);
+        self.write_analysis(&signal.finish());
+    }
+}
+
+/// A stage of an [`AudioDump`] written chunk by chunk. After the first error
+/// the rest of the stage is dropped.
+pub struct DumpStream {
+    path: PathBuf,
+    wav: Option<WavStream<BufWriter<File>>>,
+}
+
+impl DumpStream {
+    pub fn write<S: IntoSample<f32> + Copy>(&mut self, samples: &[S]) {
+        let Some(wav) = self.wav.as_mut() else {
+            return;
+        };
+        if let Err(err) = wav.write(samples) {
+            log_failure(&self.path, err);
+            self.wav = None;
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(Err(err)) = self.wav.map(WavStream::finish) {
+            log_failure(&self.path, err);
+        }
+    }
+}
+
+fn log_failure(path: &Path, err: hound::Error) {
+    eprintln!("could not write the audio dump {}: {}", path.display(), err);
+}
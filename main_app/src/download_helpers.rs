@@ -1,43 +1,739 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::Value;
 use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::segments::Segment;
+use crate::settings::DownloaderSettings;
+
+const YOUTUBE_HOSTS: &[&str] = &[
+    "youtube.com",
+    "www.youtube.com",
+    "m.youtube.com",
+    "music.youtube.com",
+    "youtube-nocookie.com",
+    "www.youtube-nocookie.com",
+];
+
+/// Reduces the many URL forms of a YouTube video (short links, embeds, shorts,
+/// extra query parameters such as playlists or timestamps) to
+/// `https://www.youtube.com/watch?v=<id>`. Returns `None` if the URL does not
+/// point to a single YouTube video.
+pub fn canonical_youtube_url(youtube_url: &str) -> Option<String> {
+    let url = parse_url(youtube_url)?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut segments = url.path_segments()?.filter(|segment| !segment.is_empty());
+
+    let video_id = if host == "youtu.be" || host == "www.youtu.be" {
+        segments.next()?.to_string()
+    } else if YOUTUBE_HOSTS.contains(&host.as_str()) {
+        match segments.next()? {
+            "watch" => url
+                .query_pairs()
+                .find(|(key, _)| key == "v")
+                .map(|(_, value)| value.into_owned())?,
+            "embed" | "shorts" | "live" | "v" => segments.next()?.to_string(),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    if !is_youtube_video_id(&video_id) {
+        return None;
+    }
+    Some(format!("https://www.youtube.com/watch?v={}", video_id))
+}
+
+/// Reduces a YouTube playlist or channel URL to one form yt-dlp can list:
+/// `https://www.youtube.com/playlist?list=<id>` for playlists and the videos
+/// tab for channels. Returns `None` for anything else, including videos that
+/// are opened from within a playlist.
+pub fn canonical_youtube_playlist_url(playlist_url: &str) -> Option<String> {
+    let url = parse_url(playlist_url)?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    if !YOUTUBE_HOSTS.contains(&host.as_str()) {
+        return None;
+    }
+    let mut segments = url.path_segments()?.filter(|segment| !segment.is_empty());
+
+    match segments.next()? {
+        "playlist" => {
+            let list_id = url
+                .query_pairs()
+                .find(|(key, _)| key == "list")
+                .map(|(_, value)| value.into_owned())?;
+            if list_id.is_empty() || !list_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return None;
+            }
+            Some(format!("https://www.youtube.com/playlist?list={}", list_id))
+        }
+        handle if handle.starts_with('@') && handle.len() > 1 => {
+            Some(format!("https://www.youtube.com/{}/videos", handle))
+        }
+        kind @ ("channel" | "c" | "user") => {
+            Some(format!("https://www.youtube.com/{}/{}/videos", kind, segments.next()?))
+        }
+        _ => None,
+    }
+}
+
+fn parse_url(url: &str) -> Option<Url> {
+    let url = url.trim();
+    match Url::parse(url) {
+        Ok(url) => Some(url),
+        Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{}", url)).ok(),
+        Err(_) => None,
+    }
+}
+
+fn is_youtube_video_id(video_id: &str) -> bool {
+    video_id.len() == 11
+        && video_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    /// The program the downloader runs is missing.
+    NotInstalled(String),
+    /// The source is missing, private, removed or refused.
+    Unavailable(String),
+    TimedOut(Duration),
+    /// The source exists but has no audio in a usable format.
+    Format(String),
+    Cancelled,
+    Io(std::io::Error),
+    Failed(String),
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::NotInstalled(program) => write!(f, "{} is not installed", program),
+            DownloadError::Unavailable(msg) => write!(f, "the source is not available: {}", msg),
+            DownloadError::TimedOut(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            DownloadError::Format(msg) => write!(f, "no usable audio: {}", msg),
+            DownloadError::Cancelled => write!(f, "cancelled"),
+            DownloadError::Io(err) => write!(f, "IO error: {}", err),
+            DownloadError::Failed(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        DownloadError::Io(err)
+    }
+}
+
+/// Fetches the audio behind a source into a file.
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    /// Saves the audio of `source` next to `output_path` and returns the file
+    /// it wrote. The audio is kept in its own container, so the extension of
+    /// `output_path` is replaced by the one of the format that was fetched.
+    /// Dropping the returned future stops the download.
+    async fn download(&self, source: &str, output_path: &Path) -> Result<PathBuf, DownloadError>;
+}
+
+/// Runs a download until it finishes, `timeout` passes or `cancel` fires.
+/// The partial file is left for the caller to remove.
+pub async fn download_with_timeout(
+    downloader: &dyn Downloader,
+    source: &str,
+    output_path: &Path,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> Result<PathBuf, DownloadError> {
+    if let Some(parent) = output_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::select! {
+        result = tokio::time::timeout(timeout, downloader.download(source, output_path)) => {
+            result.unwrap_or(Err(DownloadError::TimedOut(timeout)))
+        }
+        _ = cancel.cancelled() => Err(DownloadError::Cancelled),
+    }
+}
+
+/// One downloader per kind of source.
+#[derive(Clone)]
+pub struct Downloaders {
+    pub youtube: Arc<dyn Downloader>,
+    pub http: Arc<dyn Downloader>,
+    pub local: Arc<dyn Downloader>,
+}
+
+impl Default for Downloaders {
+    fn default() -> Self {
+        Downloaders {
+            youtube: Arc::new(YtDlpDownloader::from_settings(&crate::settings::get().downloader)),
+            http: Arc::new(HttpDownloader::default()),
+            local: Arc::new(LocalPathDownloader),
+        }
+    }
+}
+
+impl Downloaders {
+    /// YouTube links go through yt-dlp, other http(s) URLs are fetched
+    /// directly and anything else is read as a local path.
+    pub fn for_source(&self, source: &str) -> &dyn Downloader {
+        if canonical_youtube_url(source).is_some() || canonical_youtube_playlist_url(source).is_some() {
+            self.youtube.as_ref()
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            self.http.as_ref()
+        } else {
+            self.local.as_ref()
+        }
+    }
+}
+
+/// Runs a program and maps "not found" to [`DownloadError::NotInstalled`].
+/// The process is killed when the future is dropped.
+async fn run_program(command: &mut Command, program: &str) -> Result<Output, DownloadError> {
+    command.kill_on_drop(true).output().await.map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => DownloadError::NotInstalled(program.to_string()),
+        _ => DownloadError::Io(err),
+    })
+}
+
+/// Downloads YouTube audio with yt-dlp, in the container it is served in.
+/// The options mirror `[main_app.downloader]`.
+#[derive(Debug, Clone)]
+pub struct YtDlpDownloader {
+    pub program: String,
+    pub format: String,
+    pub max_bitrate_kbps: Option<u32>,
+    pub cookies_file: Option<PathBuf>,
+    pub rate_limit: Option<String>,
+    pub proxy: Option<String>,
+}
+
+impl Default for YtDlpDownloader {
+    fn default() -> Self {
+        YtDlpDownloader::from_settings(&DownloaderSettings::default())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct YoutubePlaylist {
+    pub title: String,
+    /// Canonical video URLs, in playlist order.
+    pub video_urls: Vec<String>,
+}
+
+impl YtDlpDownloader {
+    pub fn from_settings(settings: &DownloaderSettings) -> YtDlpDownloader {
+        YtDlpDownloader {
+            program: settings.yt_dlp.clone(),
+            format: settings.format.clone(),
+            max_bitrate_kbps: settings.max_bitrate_kbps,
+            cookies_file: settings.cookies_file.clone(),
+            rate_limit: settings.rate_limit.clone(),
+            proxy: settings.proxy.clone(),
+        }
+    }
+
+    /// A yt-dlp command with the options every request needs: the account
+    /// and the network to use.
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.arg("--no-warnings");
+        if let Some(cookies_file) = &self.cookies_file {
+            command.arg("--cookies").arg(cookies_file);
+        }
+        if let Some(proxy) = &self.proxy {
+            command.arg("--proxy").arg(proxy);
+        }
+        command
+    }
+
+    /// Lists the videos of a playlist or channel without downloading anything,
+    /// using yt-dlp's flat JSON output. Entries that are not videos (such as the
+    /// nested playlists of a channel) are left out.
+    pub async fn list_playlist(&self, playlist_url: &str) -> Result<YoutubePlaylist, DownloadError> {
+        let output = run_program(
+            self.command()
+                .arg("--flat-playlist")
+                .arg("--dump-single-json")
+                .arg(playlist_url),
+            &self.program,
+        )
+        .await?;
+        if !output.status.success() {
+            return Err(yt_dlp_error(&output));
+        }
+
+        let playlist: Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| DownloadError::Failed(format!("yt-dlp returned invalid JSON: {}", e)))?;
+        let video_urls = playlist["entries"]
+            .as_array()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry["id"].as_str())
+                    .filter(|video_id| is_youtube_video_id(video_id))
+                    .map(|video_id| format!("https://www.youtube.com/watch?v={}", video_id))
+                    .collect()
+            })
+            .unwrap_or_default();
 
-pub async fn download_youtube_audio(
-    youtube_url: &str,
-    output_path: &str,
-) -> Result<(), String> {
-    
-    
-    // Ensure output directory exists
-    if let Some(parent) = Path::new(output_path).parent() {
-        tokio::fs::create_dir_all(parent)
-            .await
-            .map_err(|e| format!("Failed to create output directory: {}", e))?;
-    }
-    
-    println!("🎵 Downloading audio from: {}", youtube_url);
-    println!("📁 Output path: {}", output_path);
-    let output = Command::new("yt-dlp")
-        .arg("--extract-audio")           // Extract audio only
-        .arg("--audio-format")
-        .arg("mp3")                       // Convert to mp3
-        .arg("--audio-quality")
-        .arg("0")                         // Best quality (0-9, 0 is best)
-        .arg("--output")
-        .arg(output_path)                 // Output file path
-        .arg("--no-playlist")             // Don't download playlists
-        .arg("--no-warnings")             // Suppress warnings
-        .arg("--progress")                // Show progress
-        .arg(youtube_url)                 // YouTube URL
-        .output()
-        .await
-        .map_err(|e| format!("Failed to execute yt-dlp: {}. Is yt-dlp installed?", e))?;
-    
-    if output.status.success() {
-        println!("✅ Download complete: {}", output_path);
-        Ok(())
+        Ok(YoutubePlaylist {
+            title: playlist["title"].as_str().unwrap_or(playlist_url).to_string(),
+            video_urls,
+        })
+    }
+
+    /// The chapters of a video as segments. Videos without chapters give an
+    /// empty list.
+    pub async fn chapters(&self, video_url: &str) -> Result<Vec<Segment>, DownloadError> {
+        let output = run_program(
+            self.command()
+                .arg("--dump-single-json")
+                .arg("--skip-download")
+                .arg("--no-playlist")
+                .arg(video_url),
+            &self.program,
+        )
+        .await?;
+        if !output.status.success() {
+            return Err(yt_dlp_error(&output));
+        }
+
+        let video: Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| DownloadError::Failed(format!("yt-dlp returned invalid JSON: {}", e)))?;
+        let seconds_to_ms = |value: &Value| value.as_f64().map(|seconds| (seconds * 1000.0).round() as u32);
+        Ok(video["chapters"]
+            .as_array()
+            .map(|chapters| {
+                chapters
+                    .iter()
+                    .filter_map(|chapter| {
+                        Some(Segment {
+                            title: chapter["title"].as_str().unwrap_or_default().to_string(),
+                            start_ms: seconds_to_ms(&chapter["start_time"])?,
+                            end_ms: seconds_to_ms(&chapter["end_time"]),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl Downloader for YtDlpDownloader {
+    async fn download(&self, source: &str, output_path: &Path) -> Result<PathBuf, DownloadError> {
+        println!("🎵 Downloading audio from: {}", source);
+        // yt-dlp fills in the extension of the container it downloads
+        let template = output_path.with_extension("%(ext)s");
+        let mut command = self.command();
+        command
+            .arg("--format")
+            .arg(&self.format)                // Audio only, no re-encode
+            .arg("--output")
+            .arg(&template)                   // Output file path
+            .arg("--print")
+            .arg("after_move:filepath")       // Tell us where it went
+            .arg("--no-playlist");            // Don't download playlists
+        if let Some(max_bitrate_kbps) = self.max_bitrate_kbps {
+            // the best stream that stays under the cap
+            command.arg("--format-sort").arg(format!("abr:{}", max_bitrate_kbps));
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            command.arg("--limit-rate").arg(rate_limit);
+        }
+        let output = run_program(command.arg(source), &self.program).await?;
+        if !output.status.success() {
+            return Err(yt_dlp_error(&output));
+        }
+
+        let printed = String::from_utf8_lossy(&output.stdout);
+        let path = match printed.lines().map(str::trim).rfind(|line| !line.is_empty()) {
+            Some(line) => PathBuf::from(line),
+            None => find_with_stem(output_path).await?,
+        };
+        println!("✅ Download complete: {}", path.display());
+        Ok(path)
+    }
+}
+
+/// The file written next to `output_path` with the same name and whatever
+/// extension the downloader picked.
+async fn find_with_stem(output_path: &Path) -> Result<PathBuf, DownloadError> {
+    let directory = output_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let stem = output_path.file_stem().unwrap_or_default();
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.file_stem() == Some(stem) && path.extension().is_some_and(|ext| ext != "part") {
+            return Ok(path);
+        }
+    }
+    Err(DownloadError::Failed(format!("no file was written for {}", output_path.display())))
+}
+
+/// Sorts yt-dlp failures by the messages it prints for them.
+fn yt_dlp_error(output: &Output) -> DownloadError {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let lower = stderr.to_lowercase();
+    // before the unavailable ones, "requested format is not available" is about the format
+    if ["requested format", "no video formats", "ffprobe and ffmpeg not found", "unsupported url"]
+        .iter()
+        .any(|needle| lower.contains(needle))
+    {
+        DownloadError::Format(stderr)
+    } else if ["video unavailable", "private video", "not available", "has been removed", "http error 404", "does not exist"]
+        .iter()
+        .any(|needle| lower.contains(needle))
+    {
+        DownloadError::Unavailable(stderr)
     } else {
-        let error = String::from_utf8_lossy(&output.stderr);
-        println!("ERROR DOWNLOADING {}", error);
-        Err(format!("yt-dlp failed: {}", error))
+        DownloadError::Failed(format!("yt-dlp failed: {}", stderr))
+    }
+}
+
+/// Fetches a direct link to an audio file with curl.
+pub struct HttpDownloader {
+    pub program: String,
+}
+
+impl Default for HttpDownloader {
+    fn default() -> Self {
+        HttpDownloader {
+            program: "curl".to_string(),
+        }
+    }
+}
+
+/// curl exit codes for a host that can't be resolved or reached and for an
+/// HTTP status of 400 or above (with `--fail`).
+const CURL_UNREACHABLE: &[i32] = &[6, 7, 22];
+
+#[async_trait]
+impl Downloader for HttpDownloader {
+    async fn download(&self, source: &str, output_path: &Path) -> Result<PathBuf, DownloadError> {
+        let output = run_program(
+            Command::new(&self.program)
+                .arg("--fail")
+                .arg("--location")
+                .arg("--silent")
+                .arg("--show-error")
+                .arg("--write-out")
+                .arg("%{content_type}")
+                .arg("--output")
+                .arg(output_path)
+                .arg(source),
+            &self.program,
+        )
+        .await?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        match output.status.code() {
+            Some(0) => {}
+            Some(code) if CURL_UNREACHABLE.contains(&code) => return Err(DownloadError::Unavailable(stderr)),
+            _ => return Err(DownloadError::Failed(format!("curl failed: {}", stderr))),
+        }
+
+        let content_type = String::from_utf8_lossy(&output.stdout).to_lowercase();
+        if content_type.starts_with("text/") || content_type.contains("html") || content_type.contains("json") {
+            return Err(DownloadError::Format(format!("{} is {}, not audio", source, content_type)));
+        }
+
+        let from_url = Url::parse(source).ok().and_then(|url| {
+            Path::new(url.path()).extension().map(|ext| ext.to_string_lossy().to_lowercase())
+        });
+        let extension = from_url
+            .filter(|ext| crate::ingest::SUPPORTED_EXTENSIONS.contains(&ext.as_str()))
+            .or_else(|| extension_for_content_type(&content_type).map(str::to_string));
+        match extension {
+            Some(extension) => {
+                let path = output_path.with_extension(extension);
+                tokio::fs::rename(output_path, &path).await?;
+                Ok(path)
+            }
+            None => Ok(output_path.to_path_buf()),
+        }
+    }
+}
+
+fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    Some(match mime {
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/x-m4a" | "audio/aac" => "m4a",
+        "audio/webm" | "video/webm" => "webm",
+        "audio/ogg" | "application/ogg" => "ogg",
+        "audio/opus" => "opus",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        _ => return None,
+    })
+}
+
+/// Copies an audio file from a local path or `file://` URL.
+pub struct LocalPathDownloader;
+
+#[async_trait]
+impl Downloader for LocalPathDownloader {
+    async fn download(&self, source: &str, output_path: &Path) -> Result<PathBuf, DownloadError> {
+        let path = match Url::parse(source) {
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .map_err(|_| DownloadError::Unavailable(format!("{} is not a local path", source)))?,
+            _ => PathBuf::from(source),
+        };
+        if !crate::ingest::is_supported_file(&path) {
+            return Err(DownloadError::Format(format!("{} is not a supported audio file", path.display())));
+        }
+        let output_path = output_path.with_extension(path.extension().unwrap_or_default());
+        match tokio::fs::copy(&path, &output_path).await {
+            Ok(_) => Ok(output_path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(DownloadError::Unavailable(format!("{} does not exist", path.display())))
+            }
+            Err(err) => Err(DownloadError::Io(err)),
+        }
+    }
+}
+
+/// Serves fixture files instead of downloading, optionally after a delay.
+#[cfg(test)]
+pub struct FakeDownloader {
+    pub fixtures: std::collections::HashMap<String, PathBuf>,
+    pub delay: Duration,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Downloader for FakeDownloader {
+    async fn download(&self, source: &str, output_path: &Path) -> Result<PathBuf, DownloadError> {
+        tokio::time::sleep(self.delay).await;
+        let fixture = self
+            .fixtures
+            .get(source)
+            .ok_or_else(|| DownloadError::Unavailable(format!("no fixture for {}", source)))?;
+        let output_path = output_path.with_extension(fixture.extension().unwrap_or_default());
+        tokio::fs::copy(fixture, &output_path).await?;
+        Ok(output_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("main_app_download_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fake(fixture: &Path, delay: Duration) -> FakeDownloader {
+        let fixtures = HashMap::from([("https://example.com/song.mp3".to_string(), fixture.to_path_buf())]);
+        FakeDownloader { fixtures, delay }
+    }
+
+    #[tokio::test]
+    async fn fake_downloader_serves_fixtures() {
+        let dir = temp_dir("fake");
+        let fixture = dir.join("fixture.mp3");
+        std::fs::write(&fixture, b"not really audio").unwrap();
+        let output = dir.join("out/song");
+
+        let downloader = fake(&fixture, Duration::ZERO);
+        let result = download_with_timeout(
+            &downloader,
+            "https://example.com/song.mp3",
+            &output,
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap(), dir.join("out/song.mp3"));
+        assert_eq!(std::fs::read(dir.join("out/song.mp3")).unwrap(), b"not really audio");
+
+        let missing = downloader.download("https://example.com/other.mp3", &output).await;
+        assert!(matches!(missing, Err(DownloadError::Unavailable(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_downloads_time_out_or_get_cancelled() {
+        let dir = temp_dir("slow");
+        let fixture = dir.join("fixture.mp3");
+        std::fs::write(&fixture, b"audio").unwrap();
+        let downloader = fake(&fixture, Duration::from_secs(30));
+        let source = "https://example.com/song.mp3";
+        let output = dir.join("song.mp3");
+
+        let timeout = Duration::from_millis(50);
+        let result = download_with_timeout(&downloader, source, &output, timeout, &CancellationToken::new()).await;
+        assert!(matches!(result, Err(DownloadError::TimedOut(t)) if t == timeout));
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        let result = download_with_timeout(&downloader, source, &output, Duration::from_secs(30), &cancel).await;
+        assert!(matches!(result, Err(DownloadError::Cancelled)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_paths_are_checked() {
+        let dir = temp_dir("local");
+        let output = dir.join("copy");
+
+        let missing = LocalPathDownloader.download(&dir.join("missing.wav").to_string_lossy(), &output).await;
+        assert!(matches!(missing, Err(DownloadError::Unavailable(_))));
+
+        let notes = dir.join("notes.txt");
+        std::fs::write(&notes, b"hello").unwrap();
+        let not_audio = LocalPathDownloader.download(&notes.to_string_lossy(), &output).await;
+        assert!(matches!(not_audio, Err(DownloadError::Format(_))));
+
+        let song = dir.join("song.flac");
+        std::fs::write(&song, b"flac").unwrap();
+        let url = Url::from_file_path(&song).unwrap();
+        assert_eq!(LocalPathDownloader.download(url.as_str(), &output).await.unwrap(), dir.join("copy.flac"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A yt-dlp stand-in: a shell script that writes its arguments to
+    /// `args.txt`, one per line, and then runs `body`.
+    fn fake_yt_dlp(dir: &Path, body: &str) -> YtDlpDownloader {
+        use std::os::unix::fs::PermissionsExt;
+
+        let program = dir.join("yt-dlp");
+        let script = format!("#!/bin/sh\nprintf '%s\\n' \"$@\" > '{}'\n{}\n", dir.join("args.txt").display(), body);
+        std::fs::write(&program, script).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        YtDlpDownloader {
+            program: program.to_string_lossy().into_owned(),
+            ..YtDlpDownloader::default()
+        }
+    }
+
+    fn recorded_args(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join("args.txt")).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn has_option(args: &[String], option: &str, value: &str) -> bool {
+        args.windows(2).any(|pair| pair[0] == option && pair[1] == value)
+    }
+
+    #[tokio::test]
+    async fn yt_dlp_downloads_with_the_configured_options() {
+        let dir = temp_dir("yt_dlp");
+        // writes an m4a where the output template says and prints its path
+        let body = r#"while [ $# -gt 0 ]; do [ "$1" = --output ] && out="$2"; shift; done
+dest=$(echo "$out" | sed 's/%(ext)s/m4a/')
+echo audio > "$dest"
+echo "$dest""#;
+        let downloader = YtDlpDownloader {
+            format: "bestaudio[ext=webm]".to_string(),
+            max_bitrate_kbps: Some(192),
+            cookies_file: Some(dir.join("cookies.txt")),
+            rate_limit: Some("2M".to_string()),
+            proxy: Some("socks5://127.0.0.1:1080".to_string()),
+            ..fake_yt_dlp(&dir, body)
+        };
+        let source = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+        let path = downloader.download(source, &dir.join("audio")).await.unwrap();
+        assert_eq!(path, dir.join("audio.m4a"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "audio\n");
+
+        let args = recorded_args(&dir);
+        assert!(has_option(&args, "--format", "bestaudio[ext=webm]"));
+        assert!(has_option(&args, "--format-sort", "abr:192"));
+        assert!(has_option(&args, "--cookies", &dir.join("cookies.txt").to_string_lossy()));
+        assert!(has_option(&args, "--limit-rate", "2M"));
+        assert!(has_option(&args, "--proxy", "socks5://127.0.0.1:1080"));
+        assert!(args.contains(&"--no-playlist".to_string()));
+        assert_eq!(args.last().unwrap(), source);
+
+        // without options only the format is passed
+        let downloader = fake_yt_dlp(&dir, body);
+        downloader.download(source, &dir.join("audio")).await.unwrap();
+        let args = recorded_args(&dir);
+        assert!(has_option(&args, "--format", &DownloaderSettings::default().format));
+        assert!(!args.iter().any(|arg| ["--format-sort", "--cookies", "--limit-rate", "--proxy"].contains(&arg.as_str())));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn yt_dlp_playlists_and_chapters_are_read_from_its_json() {
+        let dir = temp_dir("yt_dlp_json");
+        let body = r#"if grep -qx -- --flat-playlist "$(dirname "$0")/args.txt"; then
+echo '{"title": "Mix", "entries": [{"id": "dQw4w9WgXcQ"}, {"id": "UCnested"}, {"id": "9bZkp7q19f0"}]}'
+else
+echo '{"chapters": [{"title": "Intro", "start_time": 0.0, "end_time": 61.5}, {"title": "Outro", "start_time": 61.5}]}'
+fi"#;
+        let downloader = YtDlpDownloader {
+            proxy: Some("http://proxy:3128".to_string()),
+            ..fake_yt_dlp(&dir, body)
+        };
+
+        let playlist = downloader.list_playlist("https://www.youtube.com/playlist?list=PL1").await.unwrap();
+        assert_eq!(playlist.title, "Mix");
+        assert_eq!(
+            playlist.video_urls,
+            vec![
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+                "https://www.youtube.com/watch?v=9bZkp7q19f0".to_string(),
+            ]
+        );
+        assert!(has_option(&recorded_args(&dir), "--proxy", "http://proxy:3128"));
+
+        let chapters = downloader.chapters("https://www.youtube.com/watch?v=dQw4w9WgXcQ").await.unwrap();
+        assert_eq!(
+            chapters,
+            vec![
+                Segment { title: "Intro".to_string(), start_ms: 0, end_ms: Some(61_500) },
+                Segment { title: "Outro".to_string(), start_ms: 61_500, end_ms: None },
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn yt_dlp_failures_are_sorted_by_their_message() {
+        let dir = temp_dir("yt_dlp_errors");
+        let source = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        let failing = |message: &str| fake_yt_dlp(&dir, &format!("echo 'ERROR: {}' >&2\nexit 1", message));
+
+        let result = failing("[youtube] dQw4w9WgXcQ: Private video").download(source, &dir.join("a")).await;
+        assert!(matches!(result, Err(DownloadError::Unavailable(_))));
+        let result = failing("Requested format is not available").download(source, &dir.join("a")).await;
+        assert!(matches!(result, Err(DownloadError::Format(_))));
+        let result = failing("Something else").download(source, &dir.join("a")).await;
+        assert!(matches!(result, Err(DownloadError::Failed(msg)) if msg.contains("Something else")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_programs_are_reported() {
+        let downloader = YtDlpDownloader {
+            program: "yt-dlp-that-is-not-installed".to_string(),
+            ..YtDlpDownloader::default()
+        };
+        let result = downloader.download("https://youtu.be/dQw4w9WgXcQ", Path::new("unused.mp3")).await;
+        assert!(matches!(result, Err(DownloadError::NotInstalled(program)) if program == downloader.program));
     }
-}
\ No newline at end of file
+}
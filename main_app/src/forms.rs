@@ -2,7 +2,9 @@ use cot::form::Form;
 
 #[derive(Form)]
 pub struct MusicUploadForm{
-	pub youtube_url: String
+	pub youtube_url: String,
+	pub tracklist: Option<String>,
+	pub split_chapters: Option<bool>
 }
 
 
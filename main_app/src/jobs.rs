@@ -0,0 +1,466 @@
+//! Background workers that run ingest jobs outside of the HTTP request.
+//!
+//! Job ids go through an in-process queue; the job rows hold everything
+//! else, so jobs that were still pending when the server stopped are queued
+//! again on the next start.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use cot::db::{query, Auto, Database, Model};
+use main_app::utils::AudioBuffer;
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+use crate::debug_audio::AudioDump;
+use crate::download_helpers::{download_with_timeout, Downloaders, YoutubePlaylist};
+use crate::ingest::{self, IngestError};
+use crate::models::{IngestJob, IngestSegment, JobState, PlaylistIngest, Song};
+use crate::segments;
+use crate::shazam::Couple;
+use crate::storage::TempDir;
+
+/// Fingerprints saved between two progress updates while storing.
+const STORE_PROGRESS_BATCH: usize = 500;
+
+static QUEUE: OnceLock<mpsc::UnboundedSender<i64>> = OnceLock::new();
+
+/// Cancellation tokens of the jobs that are running right now.
+static RUNNING: OnceLock<std::sync::Mutex<HashMap<i64, CancellationToken>>> = OnceLock::new();
+
+fn running() -> std::sync::MutexGuard<'static, HashMap<i64, CancellationToken>> {
+    RUNNING.get_or_init(Default::default).lock().unwrap()
+}
+
+/// Spawns the worker pool and queues the jobs left unfinished by the last run.
+pub async fn start_workers(db: Arc<Database>, workers: usize) -> cot::Result<()> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if QUEUE.set(sender).is_err() {
+        return Ok(());
+    }
+
+    let receiver = Arc::new(Mutex::new(receiver));
+    let downloaders = Arc::new(Downloaders::default());
+    for _ in 0..workers.max(1) {
+        tokio::spawn(worker(db.clone(), downloaders.clone(), receiver.clone()));
+    }
+
+    for job in IngestJob::objects().all(&db).await? {
+        if !job.job_state().is_finished() {
+            enqueue(job.id.unwrap());
+        }
+    }
+    Ok(())
+}
+
+/// Hands a saved job to the workers. Without running workers the job stays
+/// queued until the next server start.
+pub fn enqueue(job_id: i64) {
+    match QUEUE.get() {
+        Some(sender) => {
+            if sender.send(job_id).is_err() {
+                eprintln!("ingest workers are gone, job #{} stays queued", job_id);
+            }
+        }
+        None => eprintln!("ingest workers are not running, job #{} stays queued", job_id),
+    }
+}
+
+/// Stops a job. A running job stops at its next stage (right away while
+/// downloading, between batches while storing, removing the song it was
+/// storing); a queued one is marked failed so no worker picks it up.
+/// Returns false if the job had already finished.
+pub async fn cancel(db: &Database, job: &mut IngestJob) -> bool {
+    if job.job_state().is_finished() {
+        return false;
+    }
+    let token = running().get(&job.id.unwrap()).cloned();
+    match token {
+        Some(token) => token.cancel(),
+        None => {
+            // a running job removes its upload itself; a queued one never will
+            if let Some(file_path) = &job.file_path {
+                drop(TempDir::for_file(Path::new(file_path)));
+            }
+            fail(db, job, IngestError::Cancelled.to_string()).await
+        }
+    }
+    true
+}
+
+pub async fn get_job(db: &Database, job_id: i64) -> cot::db::Result<Option<IngestJob>> {
+    let mut jobs = query!(IngestJob, $id == Auto::from(job_id)).all(db).await?;
+    Ok(if jobs.is_empty() { None } else { Some(jobs.remove(0)) })
+}
+
+/// Creates a job for every video of a playlist. Videos that are already in
+/// the catalogue or on their way into it get a skipped job instead.
+pub async fn queue_playlist(
+    db: &Database,
+    playlist_url: &str,
+    playlist: &YoutubePlaylist,
+) -> cot::db::Result<PlaylistIngest> {
+    let mut playlist_ingest = PlaylistIngest::new(playlist_url, &playlist.title, playlist.video_urls.len() as u32);
+    playlist_ingest.save(db).await?;
+    let playlist_id = playlist_ingest.id.unwrap();
+
+    let mut seen = std::collections::HashSet::new();
+    for video_url in &playlist.video_urls {
+        let mut job = IngestJob::new_youtube(video_url);
+        job.playlist_id = Some(playlist_id);
+
+        let known_song = query!(Song, $youtube_url == video_url.clone()).all(db).await?.into_iter().next();
+        let pending = !seen.insert(video_url.clone())
+            || query!(IngestJob, $youtube_url == video_url.clone())
+                .all(db)
+                .await?
+                .iter()
+                .any(|other| !other.job_state().is_finished());
+        if known_song.is_some() || pending {
+            job.song_id = known_song.map(|song| song.id.unwrap());
+            job.set_state(JobState::Skipped, 100);
+            job.save(db).await?;
+            continue;
+        }
+
+        job.save(db).await?;
+        enqueue(job.id.unwrap());
+    }
+    Ok(playlist_ingest)
+}
+
+pub async fn get_playlist(db: &Database, playlist_id: i64) -> cot::db::Result<Option<PlaylistIngest>> {
+    let mut playlists = query!(PlaylistIngest, $id == Auto::from(playlist_id)).all(db).await?;
+    Ok(if playlists.is_empty() { None } else { Some(playlists.remove(0)) })
+}
+
+/// The jobs of a playlist in playlist order.
+pub async fn playlist_jobs(db: &Database, playlist_id: i64) -> cot::db::Result<Vec<IngestJob>> {
+    let mut jobs = query!(IngestJob, $playlist_id == Some(playlist_id)).all(db).await?;
+    jobs.sort_by_key(|job| job.id.unwrap());
+    Ok(jobs)
+}
+
+async fn worker(
+    db: Arc<Database>,
+    downloaders: Arc<Downloaders>,
+    receiver: Arc<Mutex<mpsc::UnboundedReceiver<i64>>>,
+) {
+    loop {
+        let job_id = match receiver.lock().await.recv().await {
+            Some(job_id) => job_id,
+            None => return,
+        };
+
+        // a panicking job must not take the worker down with it
+        let result = tokio::spawn(run_job(db.clone(), downloaders.clone(), job_id)).await;
+        running().remove(&job_id);
+        if let Err(err) = result {
+            eprintln!("ingest job #{} crashed: {}", job_id, err);
+            if let Ok(Some(mut job)) = get_job(&db, job_id).await {
+                fail(&db, &mut job, "the ingest worker crashed".to_string()).await;
+            }
+        }
+    }
+}
+
+async fn run_job(db: Arc<Database>, downloaders: Arc<Downloaders>, job_id: i64) {
+    let mut job = match get_job(&db, job_id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(err) => {
+            eprintln!("failed to load ingest job #{}: {}", job_id, err);
+            return;
+        }
+    };
+    if job.job_state().is_finished() {
+        return;
+    }
+    // a job the server stopped while it was storing starts over
+    discard_partial_song(&db, &mut job).await;
+    let cancel = CancellationToken::new();
+    running().insert(job_id, cancel.clone());
+
+    // both downloads and uploads are only kept until the job has read them;
+    // dropping the directory removes them, also when the job panics
+    let (_temp_dir, mut audio_path) = match &job.file_path {
+        Some(file_path) => (TempDir::for_file(Path::new(file_path)), PathBuf::from(file_path)),
+        None => match TempDir::create(&format!("job-{}", job_id)) {
+            // downloads get the extension of whatever container they come in
+            Ok(temp_dir) => {
+                let audio_path = temp_dir.path().join("audio");
+                (temp_dir, audio_path)
+            }
+            Err(err) => {
+                fail(&db, &mut job, format!("failed to create a temporary directory: {}", err)).await;
+                return;
+            }
+        },
+    };
+    let result = process(&db, &downloaders, &cancel, &mut job, &mut audio_path).await;
+
+    match result {
+        Ok(song_id) => {
+            job.song_id = song_id;
+            job.storing_song_id = None;
+            job.file_path = None;
+            job.set_state(JobState::Done, 100);
+            save(&db, &mut job).await;
+        }
+        Err(err) => fail(&db, &mut job, err.to_string()).await,
+    }
+}
+
+/// Returns the id of the new song, or `None` for a segmented source, whose
+/// songs are listed with its [`IngestSegment`]s.
+async fn process(
+    db: &Arc<Database>,
+    downloaders: &Downloaders,
+    cancel: &CancellationToken,
+    job: &mut IngestJob,
+    audio_path: &mut PathBuf,
+) -> Result<Option<i64>, IngestError> {
+    if job.file_path.is_none() {
+        job.set_state(JobState::Downloading, 5);
+        save(db, job).await;
+        let timeout = Duration::from_secs(crate::settings::get().downloader.timeout_secs);
+        let downloader = downloaders.for_source(&job.youtube_url);
+        *audio_path = download_with_timeout(downloader, &job.youtube_url, audio_path, timeout, cancel).await?;
+    }
+
+    check_cancelled(cancel)?;
+    job.set_state(JobState::Decoding, 30);
+    save(db, job).await;
+    let path = audio_path.to_path_buf();
+    let dump = AudioDump::for_job(job.id.unwrap());
+    // decoded straight into the analysis signal, which is all the later
+    // stages need
+    let (content_hash, decoded) = tokio::task::spawn_blocking(move || {
+        (ingest::content_hash(&path).ok(), ingest::analysis_audio_of_file(&path, dump.as_ref()))
+    })
+    .await
+    .expect("the decoder panicked");
+    let (signal, report) = decoded?;
+    if report.packets_dropped > 0 || report.decoder_resets > 0 {
+        eprintln!("ingest job #{}: {}", job.id.unwrap(), report);
+    }
+
+    if job.is_segmented() {
+        process_segments(db, cancel, job, &signal).await?;
+        return Ok(None);
+    }
+    let mut song = new_song(job);
+    song.content_hash = content_hash;
+    let song = ingest_signal(db, cancel, job, song, signal, (50, 99)).await?;
+    Ok(Some(song.id.unwrap()))
+}
+
+/// Cuts the analysis signal of a source into the tracks of its track list and ingests each
+/// one as a song of its own. A track that fails, usually because it is
+/// already in the catalogue, doesn't stop the others.
+async fn process_segments(
+    db: &Arc<Database>,
+    cancel: &CancellationToken,
+    job: &mut IngestJob,
+    signal: &AudioBuffer<f64>,
+) -> Result<(), IngestError> {
+    let job_id = job.id.unwrap();
+    let tracklist = job.tracklist.clone().unwrap_or_default();
+    let duration_ms = signal.duration().as_millis() as u32;
+    let tracks = segments::resolve(segments::parse_tracklist(&tracklist).map_err(IngestError::Tracklist)?, duration_ms);
+    if tracks.is_empty() {
+        return Err(IngestError::Tracklist("None of the tracks start before the audio ends.".to_string()));
+    }
+
+    // a job that was interrupted keeps the tracks it already ingested
+    let mut previous: HashMap<u32, IngestSegment> = segments_of(db, job_id)
+        .await?
+        .into_iter()
+        .map(|segment| (segment.position, segment))
+        .collect();
+    let mut ingested = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        let position = i as u32 + 1;
+        let end_ms = track.end_ms.unwrap_or(duration_ms);
+        let mut segment = match previous.remove(&position) {
+            Some(segment) if segment.song_id.is_some() => {
+                ingested += 1;
+                continue;
+            }
+            Some(mut segment) => {
+                segment.error = None;
+                segment
+            }
+            None => IngestSegment::new(job_id, position, &track.title, track.start_ms, end_ms),
+        };
+
+        let progress = (50 + (49 * i / tracks.len()) as u32, 50 + (49 * (i + 1) / tracks.len()) as u32);
+        let track_signal = AudioBuffer::new(
+            segments::cut(&signal.samples, signal.sample_rate, track.start_ms, end_ms).to_vec(),
+            signal.sample_rate,
+        );
+        let mut song = new_song(job);
+        song.segment_title = Some(track.title.clone());
+        song.segment_start_ms = Some(track.start_ms);
+        song.segment_end_ms = Some(end_ms);
+        match ingest_signal(db, cancel, job, song, track_signal, progress).await {
+            Ok(song) => {
+                segment.song_id = Some(song.id.unwrap());
+                ingested += 1;
+            }
+            Err(IngestError::Cancelled) => return Err(IngestError::Cancelled),
+            Err(err) => segment.error = Some(err.to_string()),
+        }
+        segment.save(db).await?;
+        // linked to its track, the song is complete
+        if job.storing_song_id.take().is_some() {
+            save(db, job).await;
+        }
+    }
+
+    if ingested == 0 {
+        return Err(IngestError::NoSegmentIngested(tracks.len()));
+    }
+    Ok(())
+}
+
+/// The tracks of a segmented job in source order.
+pub async fn segments_of(db: &Database, job_id: i64) -> cot::db::Result<Vec<IngestSegment>> {
+    let mut segments = query!(IngestSegment, $job_id == job_id).all(db).await?;
+    segments.sort_by_key(|segment| segment.position);
+    Ok(segments)
+}
+
+fn new_song(job: &IngestJob) -> Song {
+    match &job.file_name {
+        Some(file_name) => Song::new_local_file(file_name),
+        None => Song::new(&job.youtube_url),
+    }
+}
+
+/// Fingerprints an analysis signal and saves `song` with it, moving the
+/// job's progress from `progress.0` to `progress.1`.
+async fn ingest_signal(
+    db: &Arc<Database>,
+    cancel: &CancellationToken,
+    job: &mut IngestJob,
+    mut song: Song,
+    signal: AudioBuffer<f64>,
+    progress: (u32, u32),
+) -> Result<Song, IngestError> {
+    let (from, to) = progress;
+    let storing_from = from + (to - from + 1) / 5;
+
+    check_cancelled(cancel)?;
+    job.set_state(JobState::Fingerprinting, from);
+    save(db, job).await;
+    let (fingerprints, signal) = tokio::task::spawn_blocking(move || {
+        let fingerprints = ingest::fingerprint_analysis_audio(&signal)?;
+        Ok::<_, IngestError>((fingerprints, signal))
+    })
+    .await
+    .expect("fingerprinting panicked")?;
+
+    check_cancelled(cancel)?;
+    job.set_state(JobState::Storing, storing_from);
+    save(db, job).await;
+    if let Some(duplicate) = crate::shazam::find_duplicate(&fingerprints, db)
+        .await
+        .map_err(IngestError::DuplicateCheck)?
+    {
+        return Err(IngestError::Duplicate(duplicate));
+    }
+
+    song.audio_hash = tokio::task::spawn_blocking(move || ingest::keep_audio(&signal))
+        .await
+        .expect("keeping the audio panicked");
+    song.save(db).await?;
+    let song_id = song.id.unwrap();
+    job.storing_song_id = Some(song_id);
+    if let Err(err) = store_song(db, cancel, job, song_id, fingerprints, (storing_from, to)).await {
+        discard_partial_song(db, job).await;
+        return Err(err);
+    }
+    Ok(song)
+}
+
+/// Saves the fingerprints of a saved song batch by batch. The database has
+/// no transactions to put them in, so the song is recorded on the job first
+/// and [`discard_partial_song`] removes it if storing doesn't finish.
+async fn store_song(
+    db: &Database,
+    cancel: &CancellationToken,
+    job: &mut IngestJob,
+    song_id: i64,
+    fingerprints: HashMap<u32, Couple>,
+    progress: (u32, u32),
+) -> Result<(), IngestError> {
+    let (from, to) = progress;
+    // unlike progress updates, this one has to be written
+    job.save(db).await?;
+
+    let fingerprints: Vec<(u32, Couple)> = fingerprints.into_iter().collect();
+    let mut stored = 0;
+    for batch in fingerprints.chunks(STORE_PROGRESS_BATCH) {
+        check_cancelled(cancel)?;
+        ingest::store_fingerprints(db, song_id, batch).await?;
+        stored += batch.len();
+        job.set_state(JobState::Storing, from + ((to - from) as usize * stored / fingerprints.len()) as u32);
+        save(db, job).await;
+    }
+    Ok(())
+}
+
+/// Removes the song a job didn't finish storing, along with the
+/// fingerprints it got. A failed removal is retried the next time the job
+/// fails or runs.
+async fn discard_partial_song(db: &Database, job: &mut IngestJob) {
+    let Some(song_id) = job.storing_song_id else {
+        return;
+    };
+    // a segmented job links a track to its song before it forgets the song
+    let linked = query!(IngestSegment, $song_id == Some(song_id)).exists(db).await;
+    match linked {
+        Ok(false) => {
+            if let Err(err) = ingest::delete_song(db, song_id).await {
+                eprintln!("ingest job #{}: failed to remove the partial song #{}: {}", job.id.unwrap(), song_id, err);
+                return;
+            }
+        }
+        Ok(true) => {}
+        Err(err) => {
+            eprintln!("ingest job #{}: failed to look up song #{}: {}", job.id.unwrap(), song_id, err);
+            return;
+        }
+    }
+    job.storing_song_id = None;
+    save(db, job).await;
+}
+
+fn check_cancelled(cancel: &CancellationToken) -> Result<(), IngestError> {
+    if cancel.is_cancelled() {
+        Err(IngestError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+async fn fail(db: &Database, job: &mut IngestJob, error: String) {
+    eprintln!("ingest job #{} failed: {}", job.id.unwrap(), error);
+    discard_partial_song(db, job).await;
+    let progress = job.progress;
+    job.error = Some(error);
+    job.file_path = None;
+    job.set_state(JobState::Failed, progress);
+    save(db, job).await;
+}
+
+/// Progress updates are best effort: a failed write only delays what the
+/// status page shows.
+async fn save(db: &Database, job: &mut IngestJob) {
+    if let Err(err) = job.save(db).await {
+        eprintln!("failed to update ingest job #{}: {}", job.id.unwrap(), err);
+    }
+}
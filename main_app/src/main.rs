@@ -7,94 +7,113 @@ mod views;
 mod forms;
 mod download_helpers;
 mod handlers;
+mod settings;
+mod query_store;
+mod commands;
+mod ingest;
+mod jobs;
+mod storage;
+mod audio_store;
+mod segments;
+mod debug_audio;
 
 // mod utils;
 
-use askama::Template;
+use std::time::Duration;
+
+use async_trait::async_trait;
 use cot::auth::db::DatabaseUserApp;
-use cot::cli::CliMetadata;
+use cot::cli::{Cli, CliMetadata};
+use cot::config::ProjectConfig;
 use cot::db::migrations::SyncDynMigration;
-use cot::html::Html;
 use cot::middleware::{AuthMiddleware, LiveReloadMiddleware, SessionMiddleware};
-use cot::project::{MiddlewareContext, RegisterAppsContext, RootHandler, RootHandlerBuilder};
-use cot::request::extractors::StaticFiles;
+use cot::project::{MiddlewareContext, ProjectContext, RegisterAppsContext, RootHandler, RootHandlerBuilder};
 use cot::router::{Route, Router};
 use cot::static_files::{StaticFile, StaticFilesMiddleware};
 use cot::session::db::SessionApp;
 use cot::{App, AppBuilder, Project, static_files};
-use cot::db::{model, Auto, LimitedString};
-use cot::request::extractors::RequestDb;
-use cot::db::Model;
-use cot::db::query;
-
-
-#[model]
-pub struct Link {
-    #[model(primary_key)]
-    id: Auto<i64>,
-    url: String,
-}
-
-impl Link{
-    fn new(url: &str)->Link{
-        Link{
-            id: Auto::default(),
-            url: url.to_string()
-        }
-    }
-}
-#[derive(Debug, Template)]
-#[template(path = "index.html")]
-struct IndexTemplate {
-    static_files: StaticFiles,
-}
-
-async fn index(static_files: StaticFiles, RequestDb(mut db): RequestDb) -> cot::Result<Html> {
-    let index_template = IndexTemplate { static_files };
-    let rendered = index_template.render()?;
-
-    let mut link = Link::new("abc");
-    link.save(&mut db);
-
-    let link = query!(Link, $url != String::from("sfasdf"))
-        .all(&mut db)
-        .await?;
-
-    println!("LEN S: {}", link.len());
-    Ok(Html::new(rendered))
-}
-
-// async fn myview(request: Request,  RequestDb(mut db): RequestDb) -> () {
-    // let index_template = IndexTemplate { static_files };
-    // let rendered = index_template.render()?;
-
-    // Ok(Html::new(rendered))
-// }
 
 struct MainAppApp;
 
+#[async_trait]
 impl App for MainAppApp {
     fn name(&self) -> &'static str {
         env!("CARGO_CRATE_NAME")
     }
 
+    async fn init(&self, context: &mut ProjectContext) -> cot::Result<()> {
+        models::create_indexes(context.database()).await?;
+        let stale_after = Duration::from_secs(settings::get().storage.stale_after_hours * 3600);
+        let swept = storage::sweep_stale(context.database(), stale_after).await?;
+        if swept > 0 {
+            println!("removed {} stale temporary files", swept);
+        }
+        jobs::start_workers(context.database().clone(), settings::get().jobs.workers).await?;
+        Ok(())
+    }
+
     fn migrations(&self) -> Vec<Box<SyncDynMigration>> {
         cot::db::migrations::wrap_migrations(migrations::MIGRATIONS)
     }
 
     fn router(&self) -> Router {
         Router::with_urls([
-            Route::with_handler_and_name("", index, "index"),
+            Route::with_handler_and_name("", views::index_view, "index"),
             Route::with_handler_and_name(
                 "upload/",
                 views::upload_view,
                 "upload-view"
             ),
+            Route::with_handler_and_name(
+                "upload/probe/",
+                views::probe_upload_view,
+                "probe-upload-view"
+            ),
+            Route::with_handler_and_name(
+                "upload/jobs/{job_id}/",
+                views::ingest_job_view,
+                "ingest-job-view"
+            ),
+            Route::with_handler_and_name(
+                "upload/jobs/{job_id}/json/",
+                views::ingest_job_json_view,
+                "ingest-job-json-view"
+            ),
+            Route::with_handler_and_name(
+                "upload/jobs/{job_id}/cancel/",
+                views::cancel_ingest_job_view,
+                "cancel-ingest-job-view"
+            ),
+            Route::with_handler_and_name(
+                "upload/playlists/{playlist_id}/",
+                views::playlist_ingest_view,
+                "playlist-ingest-view"
+            ),
+            Route::with_handler_and_name(
+                "upload/playlists/{playlist_id}/json/",
+                views::playlist_ingest_json_view,
+                "playlist-ingest-json-view"
+            ),
             Route::with_handler_and_name(
                 "search/",
                 views::search_view,
                 "search-view"
             ),
+            Route::with_handler_and_name(
+                "search/history/",
+                views::search_history_view,
+                "search-history-view"
+            ),
+            Route::with_handler_and_name(
+                "search/history/json/",
+                views::search_history_json_view,
+                "search-history-json-view"
+            ),
+            Route::with_handler_and_name(
+                "songs/{song_id}/preview/",
+                views::song_preview_view,
+                "song-preview-view"
+            ),
 
         ])
     }
@@ -111,6 +130,20 @@ impl Project for MainAppProject {
         cot::cli::metadata!()
     }
 
+    fn config(&self, config_name: &str) -> cot::Result<ProjectConfig> {
+        let config_content = settings::read_config_file(config_name).map_err(|err| {
+            cot::Error::internal(format!("could not read the config file `{}`: {}", config_name, err))
+        })?;
+        settings::init(&config_content).map_err(cot::Error::internal)?;
+        ProjectConfig::from_toml(&config_content)
+    }
+
+    fn register_tasks(&self, cli: &mut Cli) {
+        cli.add_task(commands::ReplayQueries);
+        cli.add_task(commands::IngestDirectory);
+        cli.add_task(commands::Refingerprint);
+    }
+
     fn register_apps(&self, apps: &mut AppBuilder, _context: &RegisterAppsContext) {
         apps.register_with_views(MainAppApp, "/",);
         apps.register(DatabaseUserApp::new());
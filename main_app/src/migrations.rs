@@ -1,15 +1,35 @@
 //! List of migrations for the current app.
 //!
-//! Generated by cot CLI 0.4.0 on 2025-11-06 17:52:18+00:00
+//! Generated by cot CLI 0.4.0 on 2026-10-21 14:35:12+00:00
 
 pub mod m_0002_auto_20251105_155235;
 pub mod m_0001_initial;
 pub mod m_0003_auto_20251105_155246;
 pub mod m_0004_auto_20251106_175218;
+pub mod m_0005_auto_20261019_101502;
+pub mod m_0006_auto_20261019_143318;
+pub mod m_0007_auto_20261019_162740;
+pub mod m_0008_auto_20261020_091214;
+pub mod m_0009_auto_20261020_113547;
+pub mod m_0010_auto_20261020_150208;
+pub mod m_0011_auto_20261021_094105;
+pub mod m_0012_auto_20261021_110327;
+pub mod m_0013_auto_20261021_143512;
+pub mod m_0014_auto_20261022_091547;
 /// The list of migrations for current app.
 pub const MIGRATIONS: &[&::cot::db::migrations::SyncDynMigration] = &[
     &m_0002_auto_20251105_155235::Migration,
     &m_0001_initial::Migration,
     &m_0003_auto_20251105_155246::Migration,
     &m_0004_auto_20251106_175218::Migration,
+    &m_0005_auto_20261019_101502::Migration,
+    &m_0006_auto_20261019_143318::Migration,
+    &m_0007_auto_20261019_162740::Migration,
+    &m_0008_auto_20261020_091214::Migration,
+    &m_0009_auto_20261020_113547::Migration,
+    &m_0010_auto_20261020_150208::Migration,
+    &m_0011_auto_20261021_094105::Migration,
+    &m_0012_auto_20261021_110327::Migration,
+    &m_0013_auto_20261021_143512::Migration,
+    &m_0014_auto_20261022_091547::Migration,
 ];
@@ -0,0 +1,93 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-19 14:33:18+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0006_auto_20261019_143318";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0005_auto_20261019_101502",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::create_model()
+            .table_name(::cot::db::Identifier::new("main_app__search_log"))
+            .fields(
+                &[
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("id"),
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .auto()
+                        .primary_key()
+                        .set_null(
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::NULLABLE,
+                        ),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("created_at"),
+                            <chrono::DateTime<chrono::FixedOffset> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<chrono::DateTime<chrono::FixedOffset> as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("user_id"),
+                            <Option<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<Option<i64> as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("session_id"),
+                            <String as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<String as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("query_duration_ms"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("sample_length"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("sample_rate"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("hash_count"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("top_matches"),
+                            <String as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<String as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("chosen_song_id"),
+                            <Option<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<Option<i64> as ::cot::db::DatabaseField>::NULLABLE),
+                ],
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _SearchLog {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub user_id: Option<i64>,
+    pub session_id: String,
+    pub query_duration_ms: u32,
+    pub sample_length: u32,
+    pub sample_rate: u32,
+    pub hash_count: u32,
+    pub top_matches: String,
+    pub chosen_song_id: Option<i64>,
+}
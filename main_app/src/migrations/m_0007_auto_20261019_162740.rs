@@ -0,0 +1,43 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-19 16:27:40+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0007_auto_20261019_162740";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0006_auto_20261019_143318",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__search_log"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("sample_path"),
+                        <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _SearchLog {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub user_id: Option<i64>,
+    pub session_id: String,
+    pub query_duration_ms: u32,
+    pub sample_length: u32,
+    pub sample_rate: u32,
+    pub hash_count: u32,
+    pub top_matches: String,
+    pub chosen_song_id: Option<i64>,
+    pub sample_path: Option<String>,
+}
@@ -0,0 +1,47 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-20 15:02:08+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0010_auto_20261020_150208";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0009_auto_20261020_113547",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__song"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("file_path"),
+                        <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__song"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("content_hash"),
+                        <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _Song {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    pub content_hash: Option<String>,
+}
@@ -0,0 +1,90 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-21 09:41:05+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0011_auto_20261021_094105";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0010_auto_20261020_150208",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::create_model()
+            .table_name(::cot::db::Identifier::new("main_app__playlist_ingest"))
+            .fields(
+                &[
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("id"),
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .auto()
+                        .primary_key()
+                        .set_null(
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::NULLABLE,
+                        ),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("created_at"),
+                            <chrono::DateTime<chrono::FixedOffset> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<chrono::DateTime<chrono::FixedOffset> as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("playlist_url"),
+                            <String as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<String as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("title"),
+                            <String as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<String as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("entry_count"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                ],
+            )
+            .build(),
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__ingest_job"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("playlist_id"),
+                        <Option<i64> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<i64> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _PlaylistIngest {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub playlist_url: String,
+    pub title: String,
+    pub entry_count: u32,
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _IngestJob {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub updated_at: chrono::DateTime<chrono::FixedOffset>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    pub state: String,
+    pub progress: u32,
+    pub error: Option<String>,
+    pub song_id: Option<i64>,
+    pub playlist_id: Option<i64>,
+}
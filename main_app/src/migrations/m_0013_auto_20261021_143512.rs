@@ -0,0 +1,154 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-21 14:35:12+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0013_auto_20261021_143512";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0012_auto_20261021_110327",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__song"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("segment_title"),
+                        <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__song"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("segment_start_ms"),
+                        <Option<u32> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<u32> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__song"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("segment_end_ms"),
+                        <Option<u32> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<u32> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__ingest_job"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("tracklist"),
+                        <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+        ::cot::db::migrations::Operation::create_model()
+            .table_name(::cot::db::Identifier::new("main_app__ingest_segment"))
+            .fields(
+                &[
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("id"),
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .auto()
+                        .primary_key()
+                        .set_null(
+                            <cot::db::Auto<i64> as ::cot::db::DatabaseField>::NULLABLE,
+                        ),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("job_id"),
+                            <i64 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<i64 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("position"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("title"),
+                            <String as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<String as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("start_ms"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("end_ms"),
+                            <u32 as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<u32 as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("song_id"),
+                            <Option<i64> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<Option<i64> as ::cot::db::DatabaseField>::NULLABLE),
+                    ::cot::db::migrations::Field::new(
+                            ::cot::db::Identifier::new("error"),
+                            <Option<String> as ::cot::db::DatabaseField>::TYPE,
+                        )
+                        .set_null(<Option<String> as ::cot::db::DatabaseField>::NULLABLE),
+                ],
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _Song {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    pub content_hash: Option<String>,
+    pub audio_hash: Option<String>,
+    pub segment_title: Option<String>,
+    pub segment_start_ms: Option<u32>,
+    pub segment_end_ms: Option<u32>,
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _IngestJob {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub updated_at: chrono::DateTime<chrono::FixedOffset>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    pub state: String,
+    pub progress: u32,
+    pub error: Option<String>,
+    pub song_id: Option<i64>,
+    pub playlist_id: Option<i64>,
+    pub tracklist: Option<String>,
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _IngestSegment {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub job_id: i64,
+    pub position: u32,
+    pub title: String,
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub song_id: Option<i64>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,45 @@
+//! Generated by cot CLI 0.4.0 on 2026-10-22 09:15:47+00:00
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Migration;
+impl ::cot::db::migrations::Migration for Migration {
+    const APP_NAME: &'static str = "main_app";
+    const MIGRATION_NAME: &'static str = "m_0014_auto_20261022_091547";
+    const DEPENDENCIES: &'static [::cot::db::migrations::MigrationDependency] = &[
+        ::cot::db::migrations::MigrationDependency::migration(
+            "main_app",
+            "m_0013_auto_20261021_143512",
+        ),
+    ];
+    const OPERATIONS: &'static [::cot::db::migrations::Operation] = &[
+        ::cot::db::migrations::Operation::add_field()
+            .table_name(::cot::db::Identifier::new("main_app__ingest_job"))
+            .field(
+                ::cot::db::migrations::Field::new(
+                        ::cot::db::Identifier::new("storing_song_id"),
+                        <Option<i64> as ::cot::db::DatabaseField>::TYPE,
+                    )
+                    .set_null(<Option<i64> as ::cot::db::DatabaseField>::NULLABLE),
+            )
+            .build(),
+    ];
+}
+
+#[derive(::core::fmt::Debug)]
+#[::cot::db::model(model_type = "migration")]
+struct _IngestJob {
+    #[model(primary_key)]
+    pub id: cot::db::Auto<i64>,
+    pub created_at: chrono::DateTime<chrono::FixedOffset>,
+    pub updated_at: chrono::DateTime<chrono::FixedOffset>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    pub state: String,
+    pub progress: u32,
+    pub error: Option<String>,
+    pub song_id: Option<i64>,
+    pub playlist_id: Option<i64>,
+    pub tracklist: Option<String>,
+    pub storing_song_id: Option<i64>,
+}
@@ -1,9 +1,16 @@
 #![allow(dead_code,unused_variables)]
 
+use chrono::{
+    DateTime,
+    FixedOffset,
+    Utc
+};
 use cot::db::{
     model,
-    Auto
+    Auto,
+    Database
 };
+use serde_json::Value;
 use serde::ser::{
     Serialize,
     Serializer,
@@ -11,12 +18,43 @@ use serde::ser::{
 };
 
 
+/// Where the audio of a song came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongSource{
+    YouTube,
+    LocalFile,
+}
+
+impl SongSource{
+    pub fn as_str(&self)->&'static str{
+        match self{
+            SongSource::YouTube => "youtube",
+            SongSource::LocalFile => "local_file",
+        }
+    }
+}
+
+/// A YouTube song keeps its video URL and no file name; a song ingested
+/// from a local file keeps the uploaded file name and an empty URL. A song
+/// cut from a longer source (a DJ set, a compilation) also keeps its track
+/// title and where it sits in the source.
 #[derive(Debug)]
 #[model]
 pub struct Song{
     #[model(primary_key)]
     pub id: Auto<i64>,
     pub youtube_url: String,
+    pub file_name: Option<String>,
+    /// Where the file was read from, only set for songs ingested from a directory.
+    pub file_path: Option<String>,
+    /// Hex SHA-256 of the ingested audio file.
+    pub content_hash: Option<String>,
+    /// Name of the song's audio in the audio store, if it was kept.
+    pub audio_hash: Option<String>,
+    /// Set for songs cut out of a longer source, see [`IngestSegment`].
+    pub segment_title: Option<String>,
+    pub segment_start_ms: Option<u32>,
+    pub segment_end_ms: Option<u32>,
 }
 
 impl Song{
@@ -24,6 +62,61 @@ impl Song{
         Song{
             id: Auto::default(),
             youtube_url: youtube_url.to_string(),
+            file_name: None,
+            file_path: None,
+            content_hash: None,
+            audio_hash: None,
+            segment_title: None,
+            segment_start_ms: None,
+            segment_end_ms: None,
+        }
+    }
+
+    pub fn new_local_file(file_name:&str)->Song{
+        Song{
+            id: Auto::default(),
+            youtube_url: String::new(),
+            file_name: Some(file_name.to_string()),
+            file_path: None,
+            content_hash: None,
+            audio_hash: None,
+            segment_title: None,
+            segment_start_ms: None,
+            segment_end_ms: None,
+        }
+    }
+
+    pub fn source(&self)->SongSource{
+        match self.file_name{
+            Some(_) => SongSource::LocalFile,
+            None => SongSource::YouTube,
+        }
+    }
+
+    /// The track title of a segment, otherwise the video URL or the file
+    /// name, whichever identifies the song.
+    pub fn title(&self)->&str{
+        match (&self.segment_title, &self.file_name){
+            (Some(segment_title), _) => segment_title,
+            (None, Some(file_name)) => file_name,
+            (None, None) => &self.youtube_url,
+        }
+    }
+
+    /// Whether the song's own audio is at hand, as its file or in the audio
+    /// store.
+    pub fn has_audio(&self)->bool{
+        self.file_path.is_some() || self.audio_hash.is_some()
+    }
+
+    /// The video URL, pointing at the start of the segment for songs cut from
+    /// a longer video.
+    pub fn youtube_link(&self)->String{
+        match self.segment_start_ms{
+            Some(start_ms) if !self.youtube_url.is_empty() => {
+                format!("{}&t={}s", self.youtube_url, start_ms / 1000)
+            }
+            _ => self.youtube_url.clone(),
         }
     }
 }
@@ -35,12 +128,29 @@ impl Serialize for Song{
     where
     S: Serializer
     {
-        let mut s = serializer.serialize_struct("Post", 3)?;
+        let mut s = serializer.serialize_struct("Post", 6)?;
         s.serialize_field("youtube_url", &self.youtube_url)?;
+        s.serialize_field("file_name", &self.file_name)?;
+        s.serialize_field("source", self.source().as_str())?;
+        s.serialize_field("segment_title", &self.segment_title)?;
+        s.serialize_field("segment_start_ms", &self.segment_start_ms)?;
+        s.serialize_field("segment_end_ms", &self.segment_end_ms)?;
         s.end()
     }
 }
 
+/// Indexes the cot migrations can't express. The statements are valid on both
+/// SQLite and PostgreSQL and safe to run on every start.
+pub async fn create_indexes(db: &Database)->cot::db::Result<()>{
+    db.raw(
+        "CREATE INDEX IF NOT EXISTS main_app__finger_print__address ON main_app__finger_print (address)"
+    ).await?;
+    Ok(())
+}
+
+/// `address` and `anchor_time_ms` are stored as signed 32 bit integers on
+/// PostgreSQL, so addresses above `i32::MAX` show up negative in the table.
+/// They round-trip unchanged through the model.
 #[model]
 pub struct FingerPrint  {
     #[model(primary_key)]
@@ -64,4 +174,328 @@ impl FingerPrint{
             song_id
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+#[model]
+pub struct SearchLog{
+    #[model(primary_key)]
+    pub id: Auto<i64>,
+    pub created_at: DateTime<FixedOffset>,
+    pub user_id: Option<i64>,
+    pub session_id: String,
+    pub query_duration_ms: u32,
+    pub sample_length: u32,
+    pub sample_rate: u32,
+    pub hash_count: u32,
+    /// JSON array of `{song_id, youtube_url, score}` objects, best match first.
+    pub top_matches: String,
+    pub chosen_song_id: Option<i64>,
+    /// Stored query audio, only set when the query store is enabled.
+    pub sample_path: Option<String>,
+}
+
+impl SearchLog{
+    /// A search of a sample that matched nothing, until the results are set.
+    pub fn new(user_id: Option<i64>, session_id: &str, sample_length: u32, sample_rate: u32)->SearchLog{
+        SearchLog{
+            id: Auto::default(),
+            created_at: Utc::now().fixed_offset(),
+            user_id,
+            session_id: session_id.to_string(),
+            query_duration_ms: 0,
+            sample_length,
+            sample_rate,
+            hash_count: 0,
+            top_matches: "[]".to_string(),
+            chosen_song_id: None,
+            sample_path: None
+        }
+    }
+
+    pub fn sample_seconds(&self)->f64{
+        if self.sample_rate == 0{
+            return 0.0;
+        }
+        self.sample_length as f64 / self.sample_rate as f64
+    }
+}
+
+impl Serialize for SearchLog{
+    fn serialize <S>(
+        &self, serializer: S
+    )->Result<S::Ok, S::Error>
+    where
+    S: Serializer
+    {
+        let top_matches: Value = serde_json::from_str(&self.top_matches)
+            .unwrap_or(Value::Array(vec![]));
+
+        let mut s = serializer.serialize_struct("SearchLog", 11)?;
+        s.serialize_field("id", &self.id.unwrap())?;
+        s.serialize_field("created_at", &self.created_at.to_rfc3339())?;
+        s.serialize_field("user_id", &self.user_id)?;
+        s.serialize_field("session_id", &self.session_id)?;
+        s.serialize_field("query_duration_ms", &self.query_duration_ms)?;
+        s.serialize_field("sample_length", &self.sample_length)?;
+        s.serialize_field("sample_rate", &self.sample_rate)?;
+        s.serialize_field("hash_count", &self.hash_count)?;
+        s.serialize_field("top_matches", &top_matches)?;
+        s.serialize_field("chosen_song_id", &self.chosen_song_id)?;
+        s.serialize_field("sample_path", &self.sample_path)?;
+        s.end()
+    }
+}
+
+/// Steps an [`IngestJob`] goes through, in order. `Done`, `Failed` and
+/// `Skipped` are final; playlist entries that are already known are created
+/// as `Skipped` and never run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState{
+    Queued,
+    Downloading,
+    Decoding,
+    Fingerprinting,
+    Storing,
+    Done,
+    Failed,
+    Skipped,
+}
+
+impl JobState{
+    pub fn as_str(&self)->&'static str{
+        match self{
+            JobState::Queued => "queued",
+            JobState::Downloading => "downloading",
+            JobState::Decoding => "decoding",
+            JobState::Fingerprinting => "fingerprinting",
+            JobState::Storing => "storing",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Skipped => "skipped",
+        }
+    }
+
+    pub fn from_str(state: &str)->Option<JobState>{
+        match state{
+            "queued" => Some(JobState::Queued),
+            "downloading" => Some(JobState::Downloading),
+            "decoding" => Some(JobState::Decoding),
+            "fingerprinting" => Some(JobState::Fingerprinting),
+            "storing" => Some(JobState::Storing),
+            "done" => Some(JobState::Done),
+            "failed" => Some(JobState::Failed),
+            "skipped" => Some(JobState::Skipped),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self)->bool{
+        matches!(self, JobState::Done | JobState::Failed | JobState::Skipped)
+    }
+}
+
+/// A song waiting for or going through the ingestion pipeline. YouTube jobs
+/// carry the video URL; local file jobs carry the uploaded file name and the
+/// path the upload was saved to until the job has read it.
+#[derive(Debug)]
+#[model]
+pub struct IngestJob{
+    #[model(primary_key)]
+    pub id: Auto<i64>,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub youtube_url: String,
+    pub file_name: Option<String>,
+    pub file_path: Option<String>,
+    /// One of the [`JobState`] names.
+    pub state: String,
+    /// 0 to 100.
+    pub progress: u32,
+    pub error: Option<String>,
+    pub song_id: Option<i64>,
+    /// The [`PlaylistIngest`] this job is an entry of.
+    pub playlist_id: Option<i64>,
+    /// Timestamped track list the source is split by, one song per track.
+    /// Chapters of a video are turned into one when the job is queued.
+    pub tracklist: Option<String>,
+    /// The song whose fingerprints are being stored. Set until the song is
+    /// complete, so whatever an interrupted job stored can be removed.
+    pub storing_song_id: Option<i64>,
+}
+
+impl IngestJob{
+    pub fn new_youtube(youtube_url: &str)->IngestJob{
+        let now = Utc::now().fixed_offset();
+        IngestJob{
+            id: Auto::default(),
+            created_at: now,
+            updated_at: now,
+            youtube_url: youtube_url.to_string(),
+            file_name: None,
+            file_path: None,
+            state: JobState::Queued.as_str().to_string(),
+            progress: 0,
+            error: None,
+            song_id: None,
+            playlist_id: None,
+            tracklist: None,
+            storing_song_id: None
+        }
+    }
+
+    pub fn new_local_file(file_name: &str, file_path: &str)->IngestJob{
+        let mut job = IngestJob::new_youtube("");
+        job.file_name = Some(file_name.to_string());
+        job.file_path = Some(file_path.to_string());
+        job
+    }
+
+    /// Whether the source is split into several songs.
+    pub fn is_segmented(&self)->bool{
+        self.tracklist.is_some()
+    }
+
+    pub fn job_state(&self)->JobState{
+        JobState::from_str(&self.state).unwrap_or(JobState::Failed)
+    }
+
+    pub fn set_state(&mut self, state: JobState, progress: u32){
+        self.state = state.as_str().to_string();
+        self.progress = progress.min(100);
+        self.updated_at = Utc::now().fixed_offset();
+    }
+
+    /// The video URL or the file name, like [`Song::title`].
+    pub fn title(&self)->&str{
+        match &self.file_name{
+            Some(file_name) => file_name,
+            None => &self.youtube_url,
+        }
+    }
+}
+
+impl Serialize for IngestJob{
+    fn serialize <S>(
+        &self, serializer: S
+    )->Result<S::Ok, S::Error>
+    where
+    S: Serializer
+    {
+        let mut s = serializer.serialize_struct("IngestJob", 12)?;
+        s.serialize_field("id", &self.id.unwrap())?;
+        s.serialize_field("created_at", &self.created_at.to_rfc3339())?;
+        s.serialize_field("updated_at", &self.updated_at.to_rfc3339())?;
+        s.serialize_field("youtube_url", &self.youtube_url)?;
+        s.serialize_field("file_name", &self.file_name)?;
+        s.serialize_field("state", &self.state)?;
+        s.serialize_field("progress", &self.progress)?;
+        s.serialize_field("finished", &self.job_state().is_finished())?;
+        s.serialize_field("error", &self.error)?;
+        s.serialize_field("song_id", &self.song_id)?;
+        s.serialize_field("playlist_id", &self.playlist_id)?;
+        s.serialize_field("segmented", &self.is_segmented())?;
+        s.end()
+    }
+}
+
+/// A playlist or channel whose videos were queued as [`IngestJob`]s.
+#[derive(Debug)]
+#[model]
+pub struct PlaylistIngest{
+    #[model(primary_key)]
+    pub id: Auto<i64>,
+    pub created_at: DateTime<FixedOffset>,
+    pub playlist_url: String,
+    pub title: String,
+    pub entry_count: u32,
+}
+
+impl PlaylistIngest{
+    pub fn new(playlist_url: &str, title: &str, entry_count: u32)->PlaylistIngest{
+        PlaylistIngest{
+            id: Auto::default(),
+            created_at: Utc::now().fixed_offset(),
+            playlist_url: playlist_url.to_string(),
+            title: title.to_string(),
+            entry_count
+        }
+    }
+}
+
+impl Serialize for PlaylistIngest{
+    fn serialize <S>(
+        &self, serializer: S
+    )->Result<S::Ok, S::Error>
+    where
+    S: Serializer
+    {
+        let mut s = serializer.serialize_struct("PlaylistIngest", 5)?;
+        s.serialize_field("id", &self.id.unwrap())?;
+        s.serialize_field("created_at", &self.created_at.to_rfc3339())?;
+        s.serialize_field("playlist_url", &self.playlist_url)?;
+        s.serialize_field("title", &self.title)?;
+        s.serialize_field("entry_count", &self.entry_count)?;
+        s.end()
+    }
+}
+
+/// One track of a segmented [`IngestJob`] and what became of it.
+#[derive(Debug)]
+#[model]
+pub struct IngestSegment{
+    #[model(primary_key)]
+    pub id: Auto<i64>,
+    pub job_id: i64,
+    /// Order within the source, from 1.
+    pub position: u32,
+    pub title: String,
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub song_id: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl IngestSegment{
+    pub fn new(job_id: i64, position: u32, title: &str, start_ms: u32, end_ms: u32)->IngestSegment{
+        IngestSegment{
+            id: Auto::default(),
+            job_id,
+            position,
+            title: title.to_string(),
+            start_ms,
+            end_ms,
+            song_id: None,
+            error: None
+        }
+    }
+
+    /// `m:ss` (or `h:mm:ss`) of the start, for showing next to the title.
+    pub fn start(&self)->String{
+        crate::segments::format_timestamp(self.start_ms)
+    }
+
+    pub fn end(&self)->String{
+        crate::segments::format_timestamp(self.end_ms)
+    }
+}
+
+impl Serialize for IngestSegment{
+    fn serialize <S>(
+        &self, serializer: S
+    )->Result<S::Ok, S::Error>
+    where
+    S: Serializer
+    {
+        let mut s = serializer.serialize_struct("IngestSegment", 8)?;
+        s.serialize_field("id", &self.id.unwrap())?;
+        s.serialize_field("job_id", &self.job_id)?;
+        s.serialize_field("position", &self.position)?;
+        s.serialize_field("title", &self.title)?;
+        s.serialize_field("start_ms", &self.start_ms)?;
+        s.serialize_field("end_ms", &self.end_ms)?;
+        s.serialize_field("song_id", &self.song_id)?;
+        s.serialize_field("error", &self.error)?;
+        s.end()
+    }
+}
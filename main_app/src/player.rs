@@ -1,7 +1,12 @@
-use rodio::{OutputStream, Sink, Source};
+use crate::utils::{AudioBuffer, WavStream};
+use rodio::cpal::FromSample;
+use rodio::source::UniformSourceIterator;
+use rodio::{OutputStream, Sample, Sink, Source};
+use std::io::{BufWriter, Cursor, Seek, Write};
 use std::time::Duration;
 use symphonia::core::audio::{AudioBufferRef, Signal};
 use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::conv::IntoSample;
 use symphonia::core::errors::Error;
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
@@ -10,30 +15,110 @@ use symphonia::core::probe::Hint;
 use std::fs::File;
 use std::path::Path;
 
-pub fn play_audio(samples: Vec<f32>, sample_rate:u32) -> Result<(), Box<dyn std::error::Error>> {
+pub fn play_audio(audio: AudioBuffer) -> Result<(), Box<dyn std::error::Error>> {
     let (_stream, stream_handle) = OutputStream::try_default()?;
     let sink = Sink::try_new(&stream_handle)?;
     
-    let source = AudioSource {
-        samples,
-        sample_rate,
-        channels: 1,
-        current_frame: 0,
-    };
-    
-    sink.append(source);
+    sink.append(AudioSource::new(audio));
     sink.sleep_until_end();
     
     Ok(())
 }
 
-struct AudioSource {
+/// Samples rendered per write when a source is rendered offline.
+const RENDER_CHUNK: usize = 4096;
+
+/// Renders what `source` would play into a 32-bit float WAV, as fast as it
+/// can be computed rather than in real time, so it works without an output
+/// device. A source whose channel count or sample rate changes midway is
+/// converted to the ones it starts with. The source has to end: limit
+/// endless ones with `take_duration`.
+pub fn render_to_writer<S, W>(source: S, writer: W) -> hound::Result<()>
+where
+    S: Source,
+    S::Item: Sample,
+    f32: FromSample<S::Item>,
+    W: Write + Seek,
+{
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let mut samples = UniformSourceIterator::<S, f32>::new(source, channels, sample_rate);
+    let mut wav = WavStream::with_channels(writer, sample_rate, channels)?;
+    let mut chunk = Vec::with_capacity(RENDER_CHUNK);
+    loop {
+        chunk.clear();
+        chunk.extend(samples.by_ref().take(RENDER_CHUNK));
+        if chunk.is_empty() {
+            break;
+        }
+        wav.write(&chunk)?;
+    }
+    wav.finish()
+}
+
+/// [`render_to_writer`] into a file.
+pub fn render_to_file<S, P>(source: S, path: P) -> hound::Result<()>
+where
+    S: Source,
+    S::Item: Sample,
+    f32: FromSample<S::Item>,
+    P: AsRef<Path>,
+{
+    render_to_writer(source, BufWriter::new(File::create(path)?))
+}
+
+/// [`render_to_writer`] into the bytes of a WAV file, such as for a response.
+pub fn render_to_wav<S>(source: S) -> hound::Result<Vec<u8>>
+where
+    S: Source,
+    S::Item: Sample,
+    f32: FromSample<S::Item>,
+{
+    let mut wav = Cursor::new(Vec::new());
+    render_to_writer(source, &mut wav)?;
+    Ok(wav.into_inner())
+}
+
+/// Fade at both ends of a [`clip`], so it doesn't start or stop with a click.
+const CLIP_FADE: Duration = Duration::from_millis(20);
+
+/// `length` of `audio` from `start` on, or as much as there is, faded in and
+/// out. Only the clip is converted to f32.
+pub fn clip<S: IntoSample<f32> + Copy>(audio: &AudioBuffer<S>, start: Duration, length: Duration) -> AudioSource {
+    let index = |time: Duration| ((time.as_secs_f64() * audio.sample_rate as f64) as usize).min(audio.len());
+    let (start, end) = (index(start), index(start.saturating_add(length)));
+    let mut samples: Vec<f32> = audio.samples[start..end].iter().map(|sample| (*sample).into_sample()).collect();
+
+    let fade = ((CLIP_FADE.as_secs_f64() * audio.sample_rate as f64) as usize).min(samples.len() / 2);
+    let last = samples.len().saturating_sub(1);
+    for i in 0..fade {
+        let gain = i as f32 / fade as f32;
+        samples[i] *= gain;
+        samples[last - i] *= gain;
+    }
+    AudioSource::new(AudioBuffer::new(samples, audio.sample_rate))
+}
+
+/// Mono samples as a rodio [`Source`], to play them or to render them
+/// offline, with any of rodio's effects applied, through [`render_to_writer`].
+pub struct AudioSource {
     samples: Vec<f32>,
     sample_rate: u32,
     channels: u16,
     current_frame: usize,
 }
 
+impl AudioSource {
+    pub fn new(audio: AudioBuffer) -> AudioSource {
+        AudioSource {
+            samples: audio.samples,
+            sample_rate: audio.sample_rate,
+            channels: 1,
+            current_frame: 0,
+        }
+    }
+}
+
 impl Iterator for AudioSource {
     type Item = f32;
 
@@ -67,3 +152,30 @@ impl Source for AudioSource {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::decode_audio_bytes;
+    use symphonia::core::probe::Hint;
+
+    #[test]
+    fn clips_render_offline_with_faded_ends() {
+        let audio = AudioBuffer::new(vec![0.5f64; 8000], 8000);
+        let source = clip(&audio, Duration::from_millis(250), Duration::from_millis(500));
+        let wav = render_to_wav(source.amplify(0.5)).unwrap();
+
+        let rendered = decode_audio_bytes(wav, &Hint::new()).unwrap();
+        assert_eq!(rendered.sample_rate, 8000);
+        assert_eq!(rendered.len(), 4000);
+        // 20 ms fades are 160 samples at 8 kHz
+        assert_eq!(rendered.samples[0], 0.0);
+        assert_eq!(rendered.samples[80], 0.125);
+        assert_eq!(rendered.samples[2000], 0.25);
+        assert_eq!(rendered.samples[3999], 0.0);
+
+        // a clip past the end gives what there is
+        assert_eq!(clip(&audio, Duration::from_millis(900), Duration::from_secs(1)).count(), 800);
+        assert_eq!(clip(&audio, Duration::from_secs(2), Duration::from_secs(1)).count(), 0);
+    }
+}
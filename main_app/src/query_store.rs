@@ -0,0 +1,78 @@
+//! Keeps the audio of search queries on disk so failed searches can be
+//! reproduced and replayed after the matching algorithm changes.
+
+use std::path::PathBuf;
+
+use main_app::utils::AudioBuffer;
+
+use crate::settings::QueryStoreSettings;
+
+#[derive(Debug)]
+pub enum QueryStoreError {
+    Io(std::io::Error),
+    Wav(hound::Error),
+}
+
+impl std::fmt::Display for QueryStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryStoreError::Io(err) => write!(f, "IO error: {}", err),
+            QueryStoreError::Wav(err) => write!(f, "WAV error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for QueryStoreError {}
+
+impl From<std::io::Error> for QueryStoreError {
+    fn from(err: std::io::Error) -> Self {
+        QueryStoreError::Io(err)
+    }
+}
+
+impl From<hound::Error> for QueryStoreError {
+    fn from(err: hound::Error) -> Self {
+        QueryStoreError::Wav(err)
+    }
+}
+
+/// Writes the query samples as a mono 32-bit float WAV named after the search log id,
+/// then removes the oldest samples above the retention limit.
+pub fn store_query_sample(
+    settings: &QueryStoreSettings,
+    search_log_id: i64,
+    audio: &AudioBuffer,
+) -> Result<PathBuf, QueryStoreError> {
+    std::fs::create_dir_all(&settings.directory)?;
+    let path = settings.directory.join(format!("{}.wav", search_log_id));
+
+    audio.write_wav(&path)?;
+
+    prune(settings)?;
+    Ok(path)
+}
+
+/// Deletes the oldest stored samples until at most `max_files` remain.
+fn prune(settings: &QueryStoreSettings) -> std::io::Result<()> {
+    let mut stored: Vec<(i64, PathBuf)> = Vec::new();
+    for entry in std::fs::read_dir(&settings.directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wav") {
+            continue;
+        }
+        // samples are named after the auto-incremented search log id, so it orders them by age
+        if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+            stored.push((id, path));
+        }
+    }
+
+    if stored.len() <= settings.max_files {
+        return Ok(());
+    }
+    stored.sort_by_key(|(id, _)| *id);
+    let excess = stored.len() - settings.max_files;
+    for (_, path) in stored.into_iter().take(excess) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Splitting one long source, such as a DJ set or a compilation, into one
+//! song per track.
+//!
+//! Tracks come from a track list pasted in the upload form, in the format
+//! YouTube descriptions use (`3:12 Artist - Title`, one per line, optionally
+//! with an end time: `3:12-7:40 Title`), or from the chapters of the video.
+
+/// A track of a longer source. Without an end it runs until the next track
+/// starts, or until the source ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub title: String,
+    pub start_ms: u32,
+    pub end_ms: Option<u32>,
+}
+
+/// Parses `m:ss`, `mm:ss` or `h:mm:ss` into milliseconds.
+pub fn parse_timestamp(text: &str) -> Option<u32> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if !(2..=3).contains(&parts.len()) || parts.iter().any(|part| part.is_empty() || part.len() > 2) {
+        return None;
+    }
+    let mut seconds = 0u32;
+    for (i, part) in parts.iter().enumerate() {
+        let value: u32 = part.parse().ok()?;
+        // everything after the first part counts up to 59
+        if i > 0 && value >= 60 {
+            return None;
+        }
+        seconds = seconds * 60 + value;
+    }
+    Some(seconds * 1000)
+}
+
+pub fn format_timestamp(ms: u32) -> String {
+    let seconds = ms / 1000;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Parses a track list. Blank lines are ignored; every other line needs a
+/// timestamp. Errors are messages for the upload page.
+pub fn parse_tracklist(text: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let segment = parse_track(line).ok_or(format!("Line {} of the track list has no timestamp: {}", number + 1, line))?;
+        if segment.end_ms.is_some_and(|end_ms| end_ms <= segment.start_ms) {
+            return Err(format!("Line {} of the track list ends before it starts: {}", number + 1, line));
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err("The track list is empty.".to_string());
+    }
+    Ok(segments)
+}
+
+/// Finds the first timestamp in a line, an optional end timestamp right after
+/// it, and takes the rest of the line as the title.
+fn parse_track(line: &str) -> Option<Segment> {
+    let timestamp = |token: &str| parse_timestamp(token.trim_matches(|c| "[]()".contains(c)));
+    let line = line.replace(['–', '—'], "-");
+    let mut tokens: Vec<&str> = Vec::new();
+    for token in line.split_whitespace() {
+        // "3:12-7:40" reads like "3:12 - 7:40"
+        match token.split_once('-') {
+            Some((start, end)) if timestamp(start).is_some() && timestamp(end).is_some() => {
+                tokens.extend([start, "-", end]);
+            }
+            _ => tokens.push(token),
+        }
+    }
+
+    let start = tokens.iter().position(|token| timestamp(token).is_some())?;
+    let start_ms = timestamp(tokens[start])?;
+    let mut end_ms = None;
+    let mut title_from = start + 1;
+    let end = tokens.get(start + 2).and_then(|token| timestamp(token));
+    if let (Some(&"-"), Some(end)) = (tokens.get(start + 1), end) {
+        end_ms = Some(end);
+        title_from = start + 3;
+    }
+
+    // numbering such as "01." or "1)" in front of the timestamp
+    let is_numbering = |token: &&str| {
+        token.len() > 1
+            && token[..token.len() - 1].chars().all(|c| c.is_ascii_digit())
+            && token.ends_with(['.', ')'])
+    };
+    let mut title: Vec<&str> = tokens[..start]
+        .iter()
+        .filter(|token| !is_numbering(token))
+        .chain(&tokens[title_from..])
+        .copied()
+        .collect();
+    let is_separator = |token: &&str| ["-", "|", ":"].contains(token);
+    while title.first().is_some_and(is_separator) {
+        title.remove(0);
+    }
+    while title.last().is_some_and(is_separator) {
+        title.pop();
+    }
+    Some(Segment { title: title.join(" "), start_ms, end_ms })
+}
+
+/// Writes segments as a track list [`parse_tracklist`] reads back.
+pub fn format_tracklist(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|segment| match segment.end_ms {
+            Some(end_ms) => format!(
+                "{}-{} {}",
+                format_timestamp(segment.start_ms),
+                format_timestamp(end_ms),
+                segment.title
+            ),
+            None => format!("{} {}", format_timestamp(segment.start_ms), segment.title),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Orders the tracks and gives every one an end: its own, the start of the
+/// next track, or the end of the source. Tracks that start after the source
+/// ends are dropped and untitled tracks are numbered.
+pub fn resolve(mut segments: Vec<Segment>, duration_ms: u32) -> Vec<Segment> {
+    segments.sort_by_key(|segment| segment.start_ms);
+    let starts: Vec<u32> = segments.iter().map(|segment| segment.start_ms).collect();
+    segments
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, mut segment)| {
+            let next_start = starts.get(i + 1).copied().unwrap_or(duration_ms);
+            let end_ms = segment.end_ms.unwrap_or(next_start).min(duration_ms);
+            if end_ms <= segment.start_ms {
+                return None;
+            }
+            segment.end_ms = Some(end_ms);
+            if segment.title.is_empty() {
+                segment.title = format!("Track {}", i + 1);
+            }
+            Some(segment)
+        })
+        .collect()
+}
+
+/// The samples between two points in time.
+pub fn cut<T>(samples: &[T], sample_rate: u32, start_ms: u32, end_ms: u32) -> &[T] {
+    let index = |ms: u32| ((ms as u64 * sample_rate as u64 / 1000) as usize).min(samples.len());
+    &samples[index(start_ms)..index(end_ms).max(index(start_ms))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(title: &str, start_ms: u32, end_ms: Option<u32>) -> Segment {
+        Segment { title: title.to_string(), start_ms, end_ms }
+    }
+
+    #[test]
+    fn track_lists_in_description_formats_are_parsed() {
+        let tracklist = "\
+            0:00 Intro\n\
+            \n\
+            01. 3:12 Artist - First Song\n\
+            [1:02:05] Second Song\n\
+            7:40-9:00 Third Song\n\
+            10:00 – 11:30 | Fourth Song\n\
+            Last Song 12:00\n";
+        assert_eq!(
+            parse_tracklist(tracklist).unwrap(),
+            vec![
+                segment("Intro", 0, None),
+                segment("Artist - First Song", 192_000, None),
+                segment("Second Song", 3_725_000, None),
+                segment("Third Song", 460_000, Some(540_000)),
+                segment("Fourth Song", 600_000, Some(690_000)),
+                segment("Last Song", 720_000, None),
+            ]
+        );
+
+        assert!(parse_tracklist("0:00 Intro\nno time here").unwrap_err().contains("Line 2"));
+        assert!(parse_tracklist("5:00-4:00 Backwards").is_err());
+        assert!(parse_tracklist("\n  \n").is_err());
+        assert_eq!(parse_timestamp("1:60"), None);
+        assert_eq!(parse_timestamp("123:00"), None);
+    }
+
+    #[test]
+    fn segments_end_where_the_next_one_starts() {
+        let segments = vec![
+            segment("", 300_000, None),
+            segment("First", 0, None),
+            segment("Short", 400_000, Some(410_000)),
+            segment("After the end", 900_000, None),
+        ];
+        assert_eq!(
+            resolve(segments, 600_000),
+            vec![
+                segment("First", 0, Some(300_000)),
+                segment("Track 2", 300_000, Some(400_000)),
+                segment("Short", 400_000, Some(410_000)),
+            ]
+        );
+
+        let samples: Vec<f32> = (0..10_000).map(|i| i as f32).collect();
+        assert_eq!(cut(&samples, 1000, 2000, 3000), &samples[2000..3000]);
+        assert!(cut(&samples, 1000, 20_000, 30_000).is_empty());
+        assert_eq!(format_timestamp(3_725_000), "1:02:05");
+        assert_eq!(format_timestamp(192_000), "3:12");
+
+        let chapters = vec![segment("Intro", 0, Some(192_000)), segment("Outro", 192_000, None)];
+        assert_eq!(parse_tracklist(&format_tracklist(&chapters)).unwrap(), chapters);
+    }
+}
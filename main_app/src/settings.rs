@@ -0,0 +1,241 @@
+//! App specific settings.
+//!
+//! These live in the `[main_app]` section of the same TOML file cot reads its
+//! own configuration from (`config/dev.toml` by default). Cot ignores the
+//! section, so both can be parsed from one file.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::shazam::preprocess::PreprocessOptions;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub query_store: QueryStoreSettings,
+    pub upload: UploadSettings,
+    pub jobs: JobSettings,
+    pub downloader: DownloaderSettings,
+    pub storage: StorageSettings,
+    pub audio_store: AudioStoreSettings,
+    pub preprocess: PreprocessSettings,
+    pub debug: DebugSettings,
+}
+
+/// Where and how many search samples are kept for later re-evaluation.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QueryStoreSettings {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// Oldest samples are deleted once the directory holds more than this many.
+    pub max_files: usize,
+}
+
+impl Default for QueryStoreSettings {
+    fn default() -> Self {
+        QueryStoreSettings {
+            enabled: false,
+            directory: PathBuf::from("query_samples"),
+            max_files: 1000,
+        }
+    }
+}
+
+/// Limits for audio files uploaded through the upload page.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadSettings {
+    pub max_file_size_mb: u64,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        UploadSettings {
+            max_file_size_mb: 100,
+        }
+    }
+}
+
+/// The background workers that run ingest jobs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobSettings {
+    /// How many jobs run at the same time.
+    pub workers: usize,
+}
+
+impl Default for JobSettings {
+    fn default() -> Self {
+        JobSettings { workers: 2 }
+    }
+}
+
+/// How audio is fetched for ingest jobs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloaderSettings {
+    /// A download running longer than this fails the job.
+    pub timeout_secs: u64,
+    /// The yt-dlp executable, a name on `PATH` or a path.
+    pub yt_dlp: String,
+    /// yt-dlp format selector for the audio stream.
+    pub format: String,
+    /// Prefer streams up to this bitrate (kbit/s) over better ones. Unset
+    /// takes the best there is.
+    pub max_bitrate_kbps: Option<u32>,
+    /// Netscape cookies file, for videos that need a signed-in account.
+    pub cookies_file: Option<PathBuf>,
+    /// Download speed cap in yt-dlp's notation, such as `500K` or `2M`.
+    pub rate_limit: Option<String>,
+    /// Proxy URL for every yt-dlp request, such as `socks5://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+}
+
+impl Default for DownloaderSettings {
+    fn default() -> Self {
+        DownloaderSettings {
+            timeout_secs: 600,
+            yt_dlp: "yt-dlp".to_string(),
+            // m4a (AAC) is decoded by Symphonia itself, anything else
+            // (usually webm/opus) goes through ffmpeg
+            format: "bestaudio[ext=m4a]/bestaudio/best".to_string(),
+            max_bitrate_kbps: None,
+            cookies_file: None,
+            rate_limit: None,
+            proxy: None,
+        }
+    }
+}
+
+/// Where files are kept on disk.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    /// Uploads and downloads wait here, one directory each, until their job
+    /// has read them.
+    pub temp_dir: PathBuf,
+    /// Anything in `temp_dir` older than this that no pending job needs is
+    /// removed when the server starts.
+    pub stale_after_hours: u64,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            temp_dir: PathBuf::from("output"),
+            stale_after_hours: 24,
+        }
+    }
+}
+
+/// Keeping the audio of ingested songs for fingerprinting them again later.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AudioStoreSettings {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// New audio is not stored once the store would grow past this.
+    pub max_size_mb: u64,
+}
+
+impl Default for AudioStoreSettings {
+    fn default() -> Self {
+        AudioStoreSettings {
+            enabled: false,
+            directory: PathBuf::from("audio_store"),
+            max_size_mb: 10 * 1024,
+        }
+    }
+}
+
+/// What is done to audio before it is fingerprinted, for songs and searches
+/// alike.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PreprocessSettings {
+    /// Off by default: hashes depend on the level of the audio, so changing
+    /// it makes the catalogue unsearchable until `refingerprint` has run,
+    /// which needs the audio store.
+    pub normalize: bool,
+    /// RMS level (dBFS) of the audio that is not silent after normalising.
+    pub target_rms_db: f64,
+    /// Quiet audio is amplified by at most this many dB.
+    pub max_gain_db: f64,
+    pub trim_silence: bool,
+    /// Audio quieter than this (dBFS) counts as silence.
+    pub silence_threshold_db: f64,
+    /// Silences inside the audio at least this long are cut out too. Unset
+    /// only trims the ends.
+    pub split_silence_secs: Option<f64>,
+}
+
+impl Default for PreprocessSettings {
+    fn default() -> Self {
+        PreprocessSettings {
+            normalize: false,
+            target_rms_db: -20.0,
+            max_gain_db: 30.0,
+            trim_silence: true,
+            silence_threshold_db: -50.0,
+            split_silence_secs: None,
+        }
+    }
+}
+
+impl PreprocessSettings {
+    pub fn options(&self) -> PreprocessOptions {
+        PreprocessOptions {
+            target_rms_db: self.normalize.then_some(self.target_rms_db),
+            max_gain_db: self.max_gain_db,
+            silence_threshold_db: self.trim_silence.then_some(self.silence_threshold_db),
+            split_silence_secs: self.split_silence_secs,
+        }
+    }
+}
+
+/// Writing the signals the fingerprinter works on to WAV files, see
+/// [`crate::debug_audio`]. Off unless `dump_dir` is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DebugSettings {
+    pub dump_dir: Option<PathBuf>,
+    /// Only these ingest jobs are dumped. Unset dumps every job.
+    pub dump_jobs: Option<Vec<i64>>,
+    /// Dump searches too, named after their search log id.
+    pub dump_searches: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    main_app: Settings,
+}
+
+/// Reads a config file the same way cot does: `config_name` is either a path
+/// or the name of a file in the `config` directory.
+pub fn read_config_file(config_name: &str) -> std::io::Result<String> {
+    match std::fs::read_to_string(config_name) {
+        Ok(content) => Ok(content),
+        Err(_) => {
+            let path = Path::new("config").join(config_name).with_extension("toml");
+            std::fs::read_to_string(path)
+        }
+    }
+}
+
+/// Parses the `[main_app]` section and makes it available through [`get`].
+/// Only the first call has any effect.
+pub fn init(config_content: &str) -> Result<(), toml::de::Error> {
+    let config: ConfigFile = toml::from_str(config_content)?;
+    let _ = SETTINGS.set(config.main_app);
+    Ok(())
+}
+
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
@@ -2,6 +2,7 @@
 
 pub mod spectogram;
 pub mod fingerprint;
+pub mod preprocess;
 
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
@@ -9,10 +10,14 @@ use std::time::{Duration, Instant};
 use std::sync::Arc;
 use cot::db::Database;
 use cot::db::query;
+use cot::db::query::{Expr, Query};
 use cot::db::Auto;
+use main_app::utils::AudioBuffer;
+use symphonia::core::conv::IntoSample;
 
 use crate::models::FingerPrint;
 use crate::models::Song;
+use spectogram::ShazamError;
 
 const TARGET_ZONE_SIZE: usize = 5;
 
@@ -20,7 +25,13 @@ const TARGET_ZONE_SIZE: usize = 5;
 pub struct Match {
     pub song_id: i64,
     pub youtube_url: String,
+    /// See [`Song::title`].
+    pub title: String,
     pub score: f64,
+    /// Where the query starts in the song, in milliseconds.
+    pub offset_ms: u32,
+    /// See [`Song::has_audio`].
+    pub has_audio: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -54,6 +65,9 @@ pub trait DatabaseClient {
     fn get_song_by_id(&self, song_id: u32) -> Result<Option<Song>, MatchError>;
 }
 
+/// Number of addresses looked up per database query.
+const COUPLE_LOOKUP_BATCH_SIZE: usize = 200;
+
 async fn get_couples(
     db: &Arc<Database>,
     addresses: &[u32]
@@ -61,16 +75,26 @@ async fn get_couples(
 {
 
     let mut results = HashMap::<u32, Vec<Couple>>::new();
-    for address in addresses{
-        let addr = *address;
-        let mut cpls = Vec::<Couple>::new();
-        for cpl in query!(FingerPrint, $address == addr).all(db).await.unwrap(){
-            cpls.push(Couple{
+    for batch in addresses.chunks(COUPLE_LOOKUP_BATCH_SIZE){
+        let filter = batch
+            .iter()
+            .map(|address| Expr::eq(Expr::field("address"), Expr::value(*address)))
+            .reduce(Expr::or);
+        let Some(filter) = filter else {
+            continue;
+        };
+
+        let fingerprints = Query::<FingerPrint>::new()
+            .filter(filter)
+            .all(db)
+            .await
+            .map_err(|e| MatchError::DatabaseError(e.to_string()))?;
+        for cpl in fingerprints{
+            results.entry(cpl.address).or_default().push(Couple{
                 anchor_time_ms: cpl.anchor_time_ms,
                 song_id: cpl.song_id
             });
         }
-        results.insert(*address, cpls);
     }
     return Ok(results);
 }
@@ -89,20 +113,17 @@ async fn get_song_by_id(
 }
 
 /// Analyzes the audio sample to find matching songs in the database.
-pub async fn find_matches(
+/// Returns the matches, the number of hashes generated from the sample and the time spent.
+pub async fn find_matches<S: IntoSample<f64> + Copy>(
     db_client: &Arc<Database>,
-    audio_sample: &[f64],
-    audio_duration: f64,
-    sample_rate: usize,
-) -> Result<(Vec<Match>, Duration), MatchError> {
+    audio_sample: &AudioBuffer<S>,
+) -> Result<(Vec<Match>, usize, Duration), MatchError> {
     let start_time = Instant::now();
 
-    let spectrogram = spectogram::spectrogram(audio_sample, sample_rate)
+    let sample_fingerprint = spectogram::analysis_signal(audio_sample)
+        .and_then(|signal| fingerprint_analysis_signal(&signal, generate_unique_id()))
         .map_err(|e| MatchError::SpectrogramError(e.to_string()))?;
 
-    let peaks = spectogram::extract_peaks(&spectrogram, audio_duration);
-    let sample_fingerprint = fingerprint::fingerprint(peaks, generate_unique_id());
-
     let mut sample_fingerprint_map: HashMap<u32, u32> = HashMap::new();
     for (address, couple) in sample_fingerprint {
         sample_fingerprint_map.insert(address, couple.anchor_time_ms);
@@ -110,7 +131,33 @@ pub async fn find_matches(
 
     let (matches, _) = find_matches_fgp(&sample_fingerprint_map, db_client).await?;
 
-    Ok((matches, start_time.elapsed()))
+    Ok((matches, sample_fingerprint_map.len(), start_time.elapsed()))
+}
+
+/// Fingerprints a signal [`spectogram::analysis_signal`] prepared, after the
+/// configured [`preprocess`]ing. Each section left is fingerprinted on its
+/// own, and anchor times count from the start of `signal`, trimmed silence
+/// included.
+pub fn fingerprint_analysis_signal(
+    signal: &AudioBuffer<f64>,
+    song_id: i64,
+) -> Result<HashMap<u32, Couple>, ShazamError> {
+    let analysis_rate = signal.sample_rate as usize;
+    let options = crate::settings::get().preprocess.options();
+    let preprocessed = preprocess::preprocess(&signal.samples, analysis_rate, &options);
+
+    let mut fingerprints = HashMap::new();
+    for section in &preprocessed.sections {
+        let spectrogram = spectogram::spectrogram_of_analysis_signal(&section.signal)?;
+        let duration = section.signal.len() as f64 / analysis_rate as f64;
+        let mut peaks = spectogram::extract_peaks(&spectrogram, duration);
+        let offset = section.offset_secs(analysis_rate);
+        for peak in &mut peaks {
+            peak.time += offset;
+        }
+        fingerprints.extend(fingerprint::fingerprint(peaks, song_id));
+    }
+    Ok(fingerprints)
 }
 
 /// Uses the sample fingerprint to find matching songs in the database.
@@ -171,8 +218,11 @@ pub async fn find_matches_fgp(
                 let timestamp = timestamps.get(&song_id).copied().unwrap_or(0);
                 match_list.push(Match {
                     song_id,
-                    youtube_url: song.youtube_url,
+                    title: song.title().to_string(),
+                    youtube_url: song.youtube_link(),
                     score,
+                    offset_ms: matches.get(&song_id).map_or(0, |times| match_offset(times)),
+                    has_audio: song.has_audio(),
                 });
             }
             None => {
@@ -188,6 +238,64 @@ pub async fn find_matches_fgp(
     Ok((match_list, start_time.elapsed()))
 }
 
+/// Width of the time offset bins used to decide whether two tracks line up.
+const DUPLICATE_OFFSET_BIN_MS: i64 = 100;
+/// Share of a new track's hashes that must line up with a stored song for it to count as a duplicate.
+const DUPLICATE_MIN_ALIGNED_RATIO: f64 = 0.2;
+
+/// A stored song that most of a new track's fingerprints line up with.
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub song_id: i64,
+    pub youtube_url: String,
+    pub title: String,
+    pub aligned_ratio: f64,
+}
+
+/// Runs the fingerprints of a track that is about to be stored through the index
+/// and returns the stored song it duplicates, if any.
+///
+/// Whole tracks produce far too many hashes for `analyze_relative_timing`, so the
+/// hashes are instead grouped by their time offset against each stored song: a copy
+/// of the same recording puts a large share of them into a single offset bin.
+pub async fn find_duplicate(
+    fingerprints: &HashMap<u32, Couple>,
+    db_client: &Arc<Database>,
+) -> Result<Option<Duplicate>, MatchError> {
+    if fingerprints.is_empty() {
+        return Ok(None);
+    }
+
+    let addresses: Vec<u32> = fingerprints.keys().copied().collect();
+    let couples_map = get_couples(db_client, &addresses).await?;
+
+    let mut offsets: HashMap<(i64, i64), usize> = HashMap::new();
+    for (address, couples) in couples_map {
+        let track_time = fingerprints[&address].anchor_time_ms as i64;
+        for couple in couples {
+            let offset_bin = (couple.anchor_time_ms as i64 - track_time).div_euclid(DUPLICATE_OFFSET_BIN_MS);
+            *offsets.entry((couple.song_id, offset_bin)).or_insert(0) += 1;
+        }
+    }
+
+    let best = offsets
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|((song_id, _), count)| (song_id, count as f64 / fingerprints.len() as f64));
+
+    match best {
+        Some((song_id, aligned_ratio)) if aligned_ratio >= DUPLICATE_MIN_ALIGNED_RATIO => {
+            Ok(get_song_by_id(db_client, song_id).await?.map(|song| Duplicate {
+                song_id,
+                title: song.title().to_string(),
+                youtube_url: song.youtube_link(),
+                aligned_ratio,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
 /// Filters out matches that don't have enough target zones to meet the specified threshold
 fn filter_matches(
     threshold: usize,
@@ -247,6 +355,22 @@ fn analyze_relative_timing(matches: &HashMap<i64, Vec<[u32; 2]>>) -> HashMap<i64
     scores
 }
 
+/// Width of the bins song-to-query time differences are counted in to find
+/// where a query lines up with a song.
+const MATCH_OFFSET_BIN_MS: i64 = 100;
+
+/// Where in the song the query starts: the most common difference between
+/// the song's and the query's anchor times of the hashes they share.
+fn match_offset(times: &[[u32; 2]]) -> u32 {
+    let mut bins: HashMap<i64, usize> = HashMap::new();
+    for [sample_time, song_time] in times {
+        *bins.entry((*song_time as i64 - *sample_time as i64).div_euclid(MATCH_OFFSET_BIN_MS)).or_insert(0) += 1;
+    }
+    bins.into_iter()
+        .max_by_key(|(bin, count)| (*count, -bin))
+        .map_or(0, |(bin, _)| (bin * MATCH_OFFSET_BIN_MS).max(0) as u32)
+}
+
 /// Generates a unique ID (placeholder - implement your own unique ID generation)
 fn generate_unique_id() -> i64 {
     use std::time::SystemTime;
@@ -257,3 +381,19 @@ fn generate_unique_id() -> i64 {
         .as_millis() as i64
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_most_common_time_difference_is_the_offset() {
+        // three hashes put the query 4.2 s into the song, one stray elsewhere
+        let times = [[0, 4200], [500, 4730], [1000, 5210], [300, 9000]];
+        assert_eq!(match_offset(&times), 4200);
+
+        // a query that starts before the song is clamped to its start, and
+        // ties go to the earlier offset
+        assert_eq!(match_offset(&[[2000, 500]]), 0);
+        assert_eq!(match_offset(&[[0, 3000], [0, 1000]]), 1000);
+    }
+}
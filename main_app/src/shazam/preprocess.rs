@@ -0,0 +1,186 @@
+//! Clean-up of the analysis signal before its spectrogram is taken. Quiet
+//! recordings are brought to a common loudness, and silence at the ends (and,
+//! if asked for, long silences inside) is cut out. Every section that is kept
+//! knows where it started, so peak times stay those of the original audio.
+
+/// Length of the blocks loudness is measured over.
+const BLOCK_SECS: f64 = 0.01;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessOptions {
+    /// RMS level in dBFS the audio is brought to, measured over the parts
+    /// that are not silent. `None` keeps the level as it is.
+    pub target_rms_db: Option<f64>,
+    /// Quiet audio is amplified by at most this much, so that a recording of
+    /// almost nothing doesn't turn into loud noise.
+    pub max_gain_db: f64,
+    /// Blocks quieter than this (dBFS) are silence. `None` keeps silence.
+    pub silence_threshold_db: Option<f64>,
+    /// Silences at least this long split the audio into sections. `None`
+    /// only trims the ends.
+    pub split_silence_secs: Option<f64>,
+}
+
+impl Default for PreprocessOptions {
+    fn default() -> Self {
+        PreprocessOptions {
+            target_rms_db: Some(-20.0),
+            max_gain_db: 30.0,
+            silence_threshold_db: Some(-50.0),
+            split_silence_secs: None,
+        }
+    }
+}
+
+/// A stretch of the signal that is fingerprinted on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Where the section starts in the signal that was preprocessed, in
+    /// samples.
+    pub offset: usize,
+    pub signal: Vec<f64>,
+}
+
+impl Section {
+    pub fn offset_secs(&self, sample_rate: usize) -> f64 {
+        self.offset as f64 / sample_rate as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessed {
+    /// In order. Empty when the whole signal is silent.
+    pub sections: Vec<Section>,
+    /// What normalising multiplied the signal by, in dB.
+    pub gain_db: f64,
+}
+
+impl Preprocessed {
+    /// Samples cut from the start of the signal.
+    pub fn leading_trim(&self) -> usize {
+        self.sections.first().map_or(0, |section| section.offset)
+    }
+}
+
+pub fn preprocess(signal: &[f64], sample_rate: usize, options: &PreprocessOptions) -> Preprocessed {
+    let ranges = match options.silence_threshold_db {
+        Some(threshold_db) => loud_ranges(signal, sample_rate, threshold_db, options.split_silence_secs),
+        None if signal.is_empty() => Vec::new(),
+        None => vec![(0, signal.len())],
+    };
+
+    let gain_db = match options.target_rms_db {
+        Some(target_db) => {
+            let (sum, count) = ranges.iter().fold((0.0, 0), |(sum, count), &(start, end)| {
+                (sum + signal[start..end].iter().map(|value| value * value).sum::<f64>(), count + end - start)
+            });
+            match rms_db(sum, count) {
+                Some(level_db) => (target_db - level_db).min(options.max_gain_db),
+                None => 0.0,
+            }
+        }
+        None => 0.0,
+    };
+    let gain = 10f64.powf(gain_db / 20.0);
+
+    let sections = ranges
+        .into_iter()
+        .map(|(start, end)| Section {
+            offset: start,
+            signal: signal[start..end].iter().map(|value| value * gain).collect(),
+        })
+        .collect();
+    Preprocessed { sections, gain_db }
+}
+
+/// The sample ranges outside silences: the ends are always trimmed, silences
+/// inside only split the signal when they last `split_silence_secs`.
+fn loud_ranges(
+    signal: &[f64],
+    sample_rate: usize,
+    threshold_db: f64,
+    split_silence_secs: Option<f64>,
+) -> Vec<(usize, usize)> {
+    let block = ((sample_rate as f64 * BLOCK_SECS) as usize).max(1);
+    let split_blocks = split_silence_secs.map(|secs| ((secs / BLOCK_SECS).ceil() as usize).max(1));
+
+    let mut ranges = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut silent_blocks = 0;
+    for (i, samples) in signal.chunks(block).enumerate() {
+        let start = i * block;
+        let loud = rms_db(samples.iter().map(|value| value * value).sum(), samples.len())
+            .is_some_and(|level_db| level_db >= threshold_db);
+        if !loud {
+            silent_blocks += 1;
+            continue;
+        }
+        match current.as_mut() {
+            Some(range) if split_blocks.is_none_or(|split| silent_blocks < split) => range.1 = start + samples.len(),
+            _ => {
+                ranges.extend(current.take());
+                current = Some((start, start + samples.len()));
+            }
+        }
+        silent_blocks = 0;
+    }
+    ranges.extend(current);
+    ranges
+}
+
+fn rms_db(sum_of_squares: f64, count: usize) -> Option<f64> {
+    let mean = sum_of_squares / count.max(1) as f64;
+    (mean > 0.0).then(|| 10.0 * mean.log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 1000;
+
+    fn tone(seconds: f64, amplitude: f64) -> Vec<f64> {
+        (0..(seconds * RATE as f64) as usize).map(|i| (i as f64 * 0.3).sin() * amplitude).collect()
+    }
+
+    #[test]
+    fn silence_is_trimmed_and_its_length_recorded() {
+        let signal = [vec![0.0; 1500], tone(2.0, 0.1), vec![0.0; 700]].concat();
+        let options = PreprocessOptions { target_rms_db: None, ..PreprocessOptions::default() };
+        let preprocessed = preprocess(&signal, RATE, &options);
+
+        assert_eq!(preprocessed.sections.len(), 1);
+        assert_eq!(preprocessed.leading_trim(), 1500);
+        assert_eq!(preprocessed.sections[0].offset_secs(RATE), 1.5);
+        assert_eq!(preprocessed.sections[0].signal, signal[1500..3500]);
+        assert_eq!(preprocessed.gain_db, 0.0);
+    }
+
+    #[test]
+    fn quiet_audio_is_normalised_up_to_the_gain_limit() {
+        let options = PreprocessOptions::default();
+
+        // a sine at amplitude 0.01 has an RMS level of about -43 dBFS
+        let quiet = preprocess(&tone(1.0, 0.01), RATE, &options);
+        assert!((quiet.gain_db - 23.0).abs() < 0.1, "{}", quiet.gain_db);
+        let signal = &quiet.sections[0].signal;
+        let level_db = rms_db(signal.iter().map(|value| value * value).sum(), signal.len()).unwrap();
+        assert!((level_db + 20.0).abs() < 0.01, "{}", level_db);
+
+        let whisper = preprocess(&tone(1.0, 0.0001), RATE, &PreprocessOptions { silence_threshold_db: None, ..options });
+        assert_eq!(whisper.gain_db, 30.0);
+    }
+
+    #[test]
+    fn only_long_silences_split_the_audio() {
+        let signal = [tone(1.0, 0.5), vec![0.0; 300], tone(1.0, 0.5), vec![0.0; 2000], tone(1.0, 0.5)].concat();
+        let options = PreprocessOptions { split_silence_secs: Some(1.0), ..PreprocessOptions::default() };
+        let preprocessed = preprocess(&signal, RATE, &options);
+
+        let offsets: Vec<usize> = preprocessed.sections.iter().map(|section| section.offset).collect();
+        assert_eq!(offsets, [0, 4300]);
+        assert_eq!(preprocessed.sections[0].signal.len(), 2300);
+
+        assert!(preprocess(&vec![0.0; 5000], RATE, &options).sections.is_empty());
+    }
+}
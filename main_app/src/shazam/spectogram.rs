@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 
+use main_app::utils::AudioBuffer;
 use num_complex::Complex;
 use realfft::RealFftPlanner;
 use std::f64::consts::PI;
+use symphonia::core::conv::IntoSample;
 
 const DSP_RATIO: usize = 4;
 const FREQ_BIN_SIZE: usize = 1024;
@@ -28,15 +30,90 @@ impl std::fmt::Display for ShazamError {
 
 impl std::error::Error for ShazamError {}
 
-pub fn spectrogram(
-    sample: &[f64],
-    sample_rate: usize,
-) -> Result<Vec<Vec<Complex<f64>>>, ShazamError> {
-    let filtered_sample = low_pass_filter(MAX_FREQ, sample_rate as f64, sample);
+pub fn spectrogram<S: IntoSample<f64> + Copy>(audio: &AudioBuffer<S>) -> Result<Vec<Vec<Complex<f64>>>, ShazamError> {
+    let analysis_sample = analysis_signal(audio)?;
+    spectrogram_of_analysis_signal(&analysis_sample.samples)
+}
+
+/// Low-passes and downsamples audio to the signal the spectrogram is computed
+/// from.
+pub fn analysis_signal<S: IntoSample<f64> + Copy>(audio: &AudioBuffer<S>) -> Result<AudioBuffer<f64>, ShazamError> {
+    let mut signal = AnalysisSignal::new(audio.sample_rate)?;
+    signal.push(audio.iter_as());
+    Ok(signal.finish())
+}
+
+/// Builds the signal of [`analysis_signal`] from audio that arrives in chunks,
+/// such as from a streaming decoder. Only the downsampled result is kept, and
+/// it comes out the same as filtering and downsampling the whole audio at once.
+pub struct AnalysisSignal {
+    /// Low-pass filter coefficient and its last output.
+    alpha: f64,
+    filtered: f64,
+    /// Input samples averaged into one output sample, and the running sum and
+    /// count of the one being built.
+    ratio: usize,
+    sum: f64,
+    count: usize,
+    analysis_rate: u32,
+    signal: Vec<f64>,
+}
+
+impl AnalysisSignal {
+    pub fn new(sample_rate: u32) -> Result<AnalysisSignal, ShazamError> {
+        let analysis_rate = sample_rate / DSP_RATIO as u32;
+        if analysis_rate == 0 {
+            return Err(ShazamError::InvalidSampleRate(
+                "Sample rates must be positive".to_string(),
+            ));
+        }
+        let rc = 1.0 / (2.0 * PI * MAX_FREQ);
+        let dt = 1.0 / sample_rate as f64;
+        Ok(AnalysisSignal {
+            alpha: dt / (rc + dt),
+            filtered: 0.0,
+            ratio: (sample_rate / analysis_rate) as usize,
+            sum: 0.0,
+            count: 0,
+            analysis_rate,
+            signal: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, samples: impl IntoIterator<Item = f64>) {
+        self.push_inspect(samples, |_| {});
+    }
+
+    /// [`push`](Self::push), handing every low-passed sample to `inspect`
+    /// before it is averaged down.
+    pub fn push_inspect(&mut self, samples: impl IntoIterator<Item = f64>, mut inspect: impl FnMut(f64)) {
+        for x in samples {
+            self.filtered = self.alpha * x + (1.0 - self.alpha) * self.filtered;
+            inspect(self.filtered);
+            self.sum += self.filtered;
+            self.count += 1;
+            if self.count == self.ratio {
+                self.signal.push(self.sum / self.ratio as f64);
+                self.sum = 0.0;
+                self.count = 0;
+            }
+        }
+    }
 
-    let downsampled_sample =
-        downsample(&filtered_sample, sample_rate, sample_rate / DSP_RATIO)?;
+    /// The signal at its lower sample rate. A partial group at the end is
+    /// averaged over what it has.
+    pub fn finish(mut self) -> AudioBuffer<f64> {
+        if self.count > 0 {
+            self.signal.push(self.sum / self.count as f64);
+        }
+        AudioBuffer::new(self.signal, self.analysis_rate)
+    }
+}
 
+/// The spectrogram of a signal [`analysis_signal`] already prepared.
+pub fn spectrogram_of_analysis_signal(
+    downsampled_sample: &[f64],
+) -> Result<Vec<Vec<Complex<f64>>>, ShazamError> {
     let num_of_windows = downsampled_sample.len() / (FREQ_BIN_SIZE - HOP_SIZE);
     let mut spectrogram_result = Vec::with_capacity(num_of_windows);
 
@@ -219,3 +296,30 @@ pub fn extract_peaks(spectrogram: &Vec<Vec<Complex<f64>>>, audio_duration: f64)
 
     peaks
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_analysis_signal_matches_the_whole_one() {
+        let sample_rate = 44100;
+        let sample: Vec<f64> = (0..sample_rate * 2 + 3)
+            .map(|i| (i as f64 * 0.031).sin() * 0.6 + (i as f64 * 0.47).sin() * 0.3)
+            .collect();
+        let filtered = low_pass_filter(MAX_FREQ, sample_rate as f64, &sample);
+        let expected = downsample(&filtered, sample_rate, sample_rate / DSP_RATIO).unwrap();
+
+        // chunk sizes that don't line up with the downsampling ratio
+        let mut signal = AnalysisSignal::new(sample_rate as u32).unwrap();
+        for chunk in sample.chunks(1021) {
+            signal.push(chunk.iter().copied());
+        }
+        let streamed = signal.finish();
+        assert_eq!(streamed.sample_rate, 11025);
+        assert_eq!(streamed.len(), expected.len());
+        assert!(streamed.samples.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-12));
+
+        assert!(AnalysisSignal::new(3).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Scratch space for audio that only lives while a job needs it.
+//!
+//! Every upload and every download gets its own directory under
+//! `[main_app.storage] temp_dir`. A [`TempDir`] removes what it owns when it
+//! is dropped, so the files go away whether the job succeeds, fails, panics or
+//! is cancelled. [`sweep_stale`] clears what a killed server left behind.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use cot::db::{Database, Model};
+
+use crate::models::IngestJob;
+use crate::my_random::random_string;
+
+pub fn temp_root() -> PathBuf {
+    crate::settings::get().storage.temp_dir.clone()
+}
+
+/// A file or directory that is deleted on drop unless it is [kept](TempDir::keep).
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    /// Creates a fresh directory named `{prefix}-{random}` under the temp root.
+    pub fn create(prefix: &str) -> io::Result<TempDir> {
+        let root = temp_root();
+        std::fs::create_dir_all(&root)?;
+        loop {
+            let path = root.join(format!("{}-{}", prefix, random_string(12)));
+            // create_dir fails if the name is taken, so two callers never share one
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(TempDir { path, keep: false }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Takes over the directory an upload was saved to by
+    /// [`create`](TempDir::create). Files stored elsewhere are owned alone,
+    /// so a file directly in the temp root never takes the root with it.
+    pub fn for_file(file_path: &Path) -> TempDir {
+        let root = temp_root();
+        let path = match file_path.parent() {
+            Some(parent) if parent != root && parent.starts_with(&root) => parent.to_path_buf(),
+            _ => file_path.to_path_buf(),
+        };
+        TempDir { path, keep: false }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the directory in place, for files that outlive the request that
+    /// wrote them (an upload waits on disk until its job runs).
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        let result = if self.path.is_dir() {
+            std::fs::remove_dir_all(&self.path)
+        } else {
+            std::fs::remove_file(&self.path)
+        };
+        match result {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                eprintln!("failed to remove {}: {}", self.path.display(), err);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Removes everything in the temp root that was last modified more than
+/// `stale_after` ago, except the uploads of jobs that still have to run.
+/// Returns how many entries were removed.
+pub async fn sweep_stale(db: &Database, stale_after: Duration) -> cot::Result<usize> {
+    let root = temp_root();
+    let pending: HashSet<PathBuf> = IngestJob::objects()
+        .all(db)
+        .await?
+        .into_iter()
+        .filter(|job| !job.job_state().is_finished())
+        .filter_map(|job| job.file_path)
+        .map(|file_path| TempDir::for_file(Path::new(&file_path)).keep())
+        .collect();
+
+    let entries = match std::fs::read_dir(&root) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(cot::Error::internal(err)),
+    };
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let age = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < stale_after || pending.contains(&path) {
+            continue;
+        }
+        drop(TempDir { path, keep: false });
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dirs_are_removed_on_drop_and_panic_unless_kept() {
+        let dir = TempDir::create("test").unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("audio.mp3"), b"audio").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+
+        let path = std::panic::catch_unwind(|| {
+            let dir = TempDir::create("test").unwrap();
+            let path = dir.path().to_path_buf();
+            std::panic::panic_any(path);
+        })
+        .unwrap_err()
+        .downcast::<PathBuf>()
+        .unwrap();
+        assert!(!path.exists());
+
+        let kept = TempDir::create("test").unwrap().keep();
+        assert!(kept.is_dir());
+        let file = kept.join("upload.wav");
+        std::fs::write(&file, b"audio").unwrap();
+        assert_eq!(TempDir::for_file(&file).path(), kept);
+        drop(TempDir::for_file(&file));
+        assert!(!kept.exists());
+    }
+
+    #[test]
+    fn files_directly_in_the_root_are_owned_alone() {
+        let file = temp_root().join("legacy.mp3");
+        assert_eq!(TempDir::for_file(&file).keep(), file);
+    }
+}
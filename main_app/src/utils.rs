@@ -19,242 +19,1243 @@ fn print_type_of<T>(obj: &T){
     println!("{}", type_name::<T>());
 }
 
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use symphonia::core::errors::SeekErrorKind;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::{MetadataLog, MetadataOptions};
+use symphonia::core::probe::{Hint, Instantiate};
+use symphonia::core::units::Time;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-pub fn fetch_audio_data<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, u32), Error> {
-    // Open the media source
-    let file = File::open(path.as_ref())?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+/// Mono audio and the rate it is sampled at. Decoders give f32 samples in
+/// [-1, 1]; the analysis signal fingerprints are taken from is f64. Code that
+/// needs another type converts sample by sample rather than copying the
+/// whole buffer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioBuffer<S = f32> {
+    pub samples: Vec<S>,
+    pub sample_rate: u32,
+}
 
-    // Create a probe hint using the file extension
-    let mut hint = Hint::new();
-    if let Some(extension) = path.as_ref().extension() {
-        if let Some(ext_str) = extension.to_str() {
-            hint.with_extension(ext_str);
+impl<S> AudioBuffer<S> {
+    pub fn new(samples: Vec<S>, sample_rate: u32) -> AudioBuffer<S> {
+        AudioBuffer { samples, sample_rate }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+impl<S: Copy> AudioBuffer<S> {
+    /// The samples converted one at a time, e.g. `f32` to `f64`.
+    pub fn iter_as<T>(&self) -> impl Iterator<Item = T> + '_
+    where
+        S: IntoSample<T>,
+    {
+        self.samples.iter().map(|sample| (*sample).into_sample())
+    }
+}
+
+impl<S: IntoSample<f32> + Copy> AudioBuffer<S> {
+    /// Writes the buffer as a 32-bit float WAV, see [`write_wav`].
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P) -> hound::Result<()> {
+        write_wav(path, &self.samples, self.sample_rate)
+    }
+}
+
+/// Decodes a file one packet at a time and yields its audio as mono f32
+/// chunks, so a long file never has to be held in memory whole and whatever
+/// consumes it can start before decoding ends. The sample rate is the same
+/// for every chunk.
+///
+/// Damaged files are decoded as far as possible: packets that fail to decode
+/// are left out, and the decoder is rebuilt when the stream starts over with
+/// new parameters. [`report`](AudioDecoder::report) tells what happened.
+pub struct AudioDecoder {
+    source: DecoderSource,
+    sample_rate: u32,
+    report: DecodeReport,
+    downmixer: Downmixer,
+    /// Index of the next decoded sample in the whole stream, at `sample_rate`.
+    position: u64,
+    /// Samples before this index are decoded but not returned.
+    start: u64,
+    /// Decoding stops at this index.
+    end: Option<u64>,
+    /// Packets dropped since the last one that decoded.
+    dropped_in_a_row: u32,
+    finished: bool,
+}
+
+/// What decoding a file produced and what it had to skip on the way.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeReport {
+    pub packets_decoded: u64,
+    /// Packets that failed to decode and were left out.
+    pub packets_dropped: u64,
+    /// How often the decoder was rebuilt because the stream asked for it,
+    /// such as at the start of each stream of a chained Ogg file.
+    pub decoder_resets: u32,
+    /// Packets at another sample rate than the first one, resampled to it.
+    pub packets_resampled: u64,
+    /// Mono samples returned, at `sample_rate`.
+    pub frames: u64,
+    pub sample_rate: u32,
+}
+
+impl DecodeReport {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+impl std::fmt::Display for DecodeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}s decoded from {} packets", self.duration().as_secs_f64(), self.packets_decoded)?;
+        if self.packets_dropped > 0 {
+            write!(f, ", {} corrupt packets dropped", self.packets_dropped)?;
+        }
+        if self.decoder_resets > 0 {
+            write!(f, ", {} decoder resets", self.decoder_resets)?;
         }
+        if self.packets_resampled > 0 {
+            write!(f, ", {} packets resampled", self.packets_resampled)?;
+        }
+        Ok(())
     }
+}
 
-    // Probe the media source
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+/// A file is given up on once this many packets in a row fail to decode;
+/// there is nothing left to recover by then.
+const MAX_DROPPED_IN_A_ROW: u32 = 100;
 
-    let mut format = probed.format;
+/// Only the first few dropped packets of a file are logged, the report
+/// counts all of them.
+const MAX_LOGGED_DROPS: u64 = 3;
+
+enum DecoderSource {
+    Symphonia {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+    },
+    /// Raw stereo f32le frames on ffmpeg's stdout. A frame can be split
+    /// across two reads, so the bytes after the last whole one wait for the
+    /// next read.
+    Ffmpeg {
+        child: Child,
+        stdout: ChildStdout,
+        leftover: Vec<u8>,
+        /// The start of what ffmpeg wrote to stderr, read on a thread of its own.
+        stderr: Option<JoinHandle<String>>,
+    },
+}
+
+/// How many bytes of ffmpeg output make up one chunk at most.
+const FFMPEG_CHUNK_BYTES: usize = 64 * 1024;
+/// How much of ffmpeg's error output ends up in the error it fails with.
+const FFMPEG_STDERR_BYTES: u64 = 4 * 1024;
+
+/// ffmpeg writes two f32 channels per frame, which are downmixed here like
+/// the output of any other decoder.
+const FFMPEG_FRAME_BYTES: usize = 2 * 4;
+
+impl AudioDecoder {
+    /// Opens a file with Symphonia.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AudioDecoder, Error> {
+        // Open the media source
+        let file = File::open(path.as_ref())?;
+
+        // Create a probe hint using the file extension
+        let extension = path.as_ref().extension().and_then(|ext| ext.to_str());
+        AudioDecoder::from_source(Box::new(file), &format_hint(extension, None))
+    }
+
+    /// Decodes audio that is already in memory, such as an upload.
+    pub fn from_bytes<B>(bytes: B, hint: &Hint) -> Result<AudioDecoder, Error>
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        AudioDecoder::from_source(Box::new(std::io::Cursor::new(bytes)), hint)
+    }
 
+    /// Decodes from any reader that can seek.
+    pub fn from_reader<R>(reader: R, hint: &Hint) -> Result<AudioDecoder, Error>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        AudioDecoder::from_source(Box::new(SeekableSource(reader)), hint)
+    }
+
+    /// Decodes from a reader that can't seek, such as a pipe or a network
+    /// stream. The format can only be guessed from the first bytes, so pass
+    /// what is known about it in `hint`. Formats that keep their index at the
+    /// end (mp4 usually does) can't be read this way.
+    pub fn from_stream<R>(reader: R, hint: &Hint) -> Result<AudioDecoder, Error>
+    where
+        R: Read + Send + Sync + 'static,
+    {
+        AudioDecoder::from_source(Box::new(ReadOnlySource::new(reader)), hint)
+    }
+
+    fn from_source(source: Box<dyn MediaSource>, hint: &Hint) -> Result<AudioDecoder, Error> {
+        let mss = MediaSourceStream::new(source, Default::default());
+
+        // Probe the media source
+        let probed = symphonia::default::get_probe()
+            .format(hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+
+        let format = probed.format;
+        let (track_id, decoder, sample_rate) = track_decoder(format.as_ref())?;
+        Ok(AudioDecoder::new(DecoderSource::Symphonia { format, decoder, track_id }, sample_rate))
+    }
+
+    fn new(source: DecoderSource, sample_rate: u32) -> AudioDecoder {
+        AudioDecoder {
+            source,
+            sample_rate,
+            report: DecodeReport { sample_rate, ..DecodeReport::default() },
+            downmixer: Downmixer::new(ChannelSelection::default()),
+            position: 0,
+            start: 0,
+            end: None,
+            dropped_in_a_row: 0,
+            finished: false,
+        }
+    }
+
+    /// Lets ffmpeg decode the file to raw stereo f32 samples at
+    /// [`FFMPEG_SAMPLE_RATE`], read and downmixed as it writes them.
+    pub fn open_with_ffmpeg(path: &Path) -> Result<AudioDecoder, Error> {
+        let child = ffmpeg_command(path.as_os_str()).stdin(Stdio::null()).spawn()?;
+        Ok(AudioDecoder::from_ffmpeg(child))
+    }
+
+    /// Lets ffmpeg decode audio that is in memory, fed to it through stdin.
+    pub fn from_bytes_with_ffmpeg<B>(bytes: B) -> Result<AudioDecoder, Error>
+    where
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        let mut child = ffmpeg_command("pipe:0".as_ref()).stdin(Stdio::piped()).spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // written from a thread of its own, ffmpeg only reads more once its
+        // output is read
+        std::thread::spawn(move || {
+            // a failed write means ffmpeg stopped early, its exit status tells why
+            let _ = stdin.write_all(bytes.as_ref());
+        });
+        Ok(AudioDecoder::from_ffmpeg(child))
+    }
+
+    fn from_ffmpeg(mut child: Child) -> AudioDecoder {
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut pipe = child.stderr.take().expect("stderr is piped");
+        // stderr is drained while stdout is read, as ffmpeg stops writing
+        // either once the other's pipe is full
+        let stderr = std::thread::spawn(move || {
+            let mut kept = Vec::new();
+            let _ = pipe.by_ref().take(FFMPEG_STDERR_BYTES).read_to_end(&mut kept);
+            let _ = std::io::copy(&mut pipe, &mut std::io::sink());
+            String::from_utf8_lossy(&kept).into_owned()
+        });
+        AudioDecoder::new(
+            DecoderSource::Ffmpeg { child, stdout, leftover: Vec::new(), stderr: Some(stderr) },
+            FFMPEG_SAMPLE_RATE,
+        )
+    }
+
+    /// Opens a file with Symphonia and, for codecs or containers Symphonia
+    /// doesn't support (such as opus in webm), with ffmpeg instead.
+    pub fn open_with_fallback<P: AsRef<Path>>(path: P) -> Result<AudioDecoder, Error> {
+        match AudioDecoder::open(path.as_ref()) {
+            Err(Error::Unsupported(reason)) => AudioDecoder::open_with_ffmpeg(path.as_ref()).map_err(|err| match err {
+                // without ffmpeg the original reason is the more useful one
+                Error::IoError(ref io) if io.kind() == std::io::ErrorKind::NotFound => Error::Unsupported(reason),
+                err => err,
+            }),
+            result => result,
+        }
+    }
+
+    /// [`from_bytes`](AudioDecoder::from_bytes), falling back to ffmpeg like
+    /// [`open_with_fallback`](AudioDecoder::open_with_fallback). Browsers
+    /// record opus, which only ffmpeg decodes.
+    pub fn from_bytes_with_fallback<B>(bytes: B, hint: &Hint) -> Result<AudioDecoder, Error>
+    where
+        B: AsRef<[u8]> + Clone + Send + Sync + 'static,
+    {
+        match AudioDecoder::from_bytes(bytes.clone(), hint) {
+            Err(Error::Unsupported(reason)) => AudioDecoder::from_bytes_with_ffmpeg(bytes).map_err(|err| match err {
+                Error::IoError(ref io) if io.kind() == std::io::ErrorKind::NotFound => Error::Unsupported(reason),
+                err => err,
+            }),
+            result => result,
+        }
+    }
+
+    /// Keeps `selection` of the channels instead of their average.
+    pub fn with_channels(mut self, selection: ChannelSelection) -> AudioDecoder {
+        self.downmixer = Downmixer::new(selection);
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Continues decoding at `time` from the start of the stream. Symphonia
+    /// seeks there when the container allows it; otherwise, and with ffmpeg,
+    /// the samples up to `time` are decoded and thrown away, which only works
+    /// forward. Either way the next sample returned is the one at `time`.
+    pub fn seek(&mut self, time: Duration) -> Result<(), Error> {
+        let target = self.sample_index(time);
+        if let DecoderSource::Symphonia { format, decoder, track_id } = &mut self.source {
+            let seek_to = SeekTo::Time { time: Time::from(time.as_secs_f64()), track_id: Some(*track_id) };
+            match format.seek(SeekMode::Accurate, seek_to) {
+                Ok(seeked) => {
+                    decoder.reset();
+                    // the seek can land before the requested time, decoding
+                    // from there on is what makes it sample-accurate
+                    let early = seeked.required_ts.saturating_sub(seeked.actual_ts);
+                    let time_base = format
+                        .tracks()
+                        .iter()
+                        .find(|track| track.id == *track_id)
+                        .and_then(|track| track.codec_params.time_base);
+                    let early = match time_base {
+                        Some(time_base) => {
+                            let time = time_base.calc_time(early);
+                            ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+                        }
+                        None => early,
+                    };
+                    self.position = target.saturating_sub(early);
+                    self.start = target;
+                    self.finished = false;
+                    return Ok(());
+                }
+                Err(Error::SeekError(SeekErrorKind::OutOfRange)) => {
+                    self.finished = true;
+                    return Ok(());
+                }
+                Err(Error::SeekError(_) | Error::Unsupported(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if target < self.position {
+            return Err(Error::SeekError(SeekErrorKind::ForwardOnly));
+        }
+        self.start = target;
+        Ok(())
+    }
+
+    /// Stops decoding at `time` from the start of the stream.
+    pub fn stop_at(&mut self, time: Duration) {
+        self.end = Some(self.sample_index(time));
+    }
+
+    fn sample_index(&self, time: Duration) -> u64 {
+        (time.as_secs_f64() * self.sample_rate as f64).round() as u64
+    }
+
+    /// What was decoded so far, complete once the decoder is exhausted.
+    pub fn report(&self) -> &DecodeReport {
+        &self.report
+    }
+
+    /// Decodes everything that is left into one buffer.
+    pub fn collect_samples(self) -> Result<AudioBuffer, Error> {
+        let (audio, _) = self.collect_with_report()?;
+        Ok(audio)
+    }
+
+    /// [`collect_samples`](AudioDecoder::collect_samples) with the report of
+    /// the whole decode.
+    pub fn collect_with_report(mut self) -> Result<(AudioBuffer, DecodeReport), Error> {
+        let mut samples = Vec::new();
+        for chunk in &mut self {
+            samples.extend(chunk?);
+        }
+        Ok((AudioBuffer::new(samples, self.sample_rate), self.report.clone()))
+    }
+
+    /// The next decoded samples that fall between `start` and `end`.
+    fn next_chunk(&mut self) -> Result<Option<Vec<f32>>, Error> {
+        loop {
+            if self.end.is_some_and(|end| self.position >= end) {
+                return Ok(None);
+            }
+            let Some(mut samples) = self.decode_chunk()? else {
+                return Ok(None);
+            };
+            let chunk_start = self.position;
+            self.position += samples.len() as u64;
+            if let Some(end) = self.end {
+                samples.truncate(end.saturating_sub(chunk_start) as usize);
+            }
+            samples.drain(..(self.start.saturating_sub(chunk_start) as usize).min(samples.len()));
+            if !samples.is_empty() {
+                self.report.frames += samples.len() as u64;
+                return Ok(Some(samples));
+            }
+        }
+    }
+
+    fn decode_chunk(&mut self) -> Result<Option<Vec<f32>>, Error> {
+        let samples = match &mut self.source {
+            DecoderSource::Symphonia { format, decoder, track_id } => loop {
+                let packet = match format.next_packet() {
+                    Ok(packet) => packet,
+                    Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                    // a new stream starts, its track and parameters may differ
+                    Err(Error::ResetRequired) => {
+                        (*track_id, *decoder, _) = track_decoder(format.as_ref())?;
+                        self.report.decoder_resets += 1;
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                // Skip packets that don't belong to our track
+                if packet.track_id() != *track_id {
+                    continue;
+                }
+
+                // Decode the packet, convert to f32 and mix to mono if needed
+                let decoded = match decoder.decode(&packet) {
+                    Ok(decoded) => decoded,
+                    Err(Error::DecodeError(reason)) => {
+                        self.report.packets_dropped += 1;
+                        self.dropped_in_a_row += 1;
+                        if self.dropped_in_a_row >= MAX_DROPPED_IN_A_ROW {
+                            return Err(Error::DecodeError(reason));
+                        }
+                        if self.report.packets_dropped <= MAX_LOGGED_DROPS {
+                            eprintln!("skipping a packet that failed to decode: {}", reason);
+                        }
+                        continue;
+                    }
+                    Err(Error::ResetRequired) => {
+                        *decoder = symphonia::default::get_codecs()
+                            .make(decoder.codec_params(), &DecoderOptions::default())?;
+                        self.report.decoder_resets += 1;
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                self.dropped_in_a_row = 0;
+                self.report.packets_decoded += 1;
+
+                let spec = *decoded.spec();
+                let mut samples = Vec::with_capacity(decoded.frames());
+                self.downmixer.downmix(&decoded, &mut samples);
+                if spec.rate != self.sample_rate && spec.rate > 0 {
+                    samples = resample_linear(&samples, spec.rate, self.sample_rate);
+                    self.report.packets_resampled += 1;
+                }
+                if !samples.is_empty() {
+                    break samples;
+                }
+            },
+            DecoderSource::Ffmpeg { child, stdout, leftover, stderr } => loop {
+                let mut buffer = vec![0u8; FFMPEG_CHUNK_BYTES];
+                let read = stdout.read(&mut buffer)?;
+                if read == 0 {
+                    let status = child.wait()?;
+                    if !status.success() {
+                        let stderr = stderr.take().and_then(|reader| reader.join().ok()).unwrap_or_default();
+                        let message = match stderr.trim() {
+                            "" => format!("ffmpeg failed: {}", status),
+                            stderr => format!("ffmpeg failed: {}", stderr),
+                        };
+                        return Err(Error::IoError(std::io::Error::other(message)));
+                    }
+                    return Ok(None);
+                }
+
+                leftover.extend_from_slice(&buffer[..read]);
+                let whole = leftover.len() / FFMPEG_FRAME_BYTES * FFMPEG_FRAME_BYTES;
+                let selection = self.downmixer.selection();
+                let samples: Vec<f32> = leftover[..whole]
+                    .chunks_exact(FFMPEG_FRAME_BYTES)
+                    .map(|frame| {
+                        let left = f32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]);
+                        let right = f32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+                        selection.sample_of(&[left, right])
+                    })
+                    .collect();
+                leftover.drain(..whole);
+                if !samples.is_empty() {
+                    break samples;
+                }
+            },
+        };
+        Ok(Some(samples))
+    }
+}
+
+/// Finds the first audio track and makes a decoder for it. Returns the
+/// track id, the decoder and the track's sample rate.
+fn track_decoder(format: &dyn FormatReader) -> Result<(u32, Box<dyn Decoder>, u32), Error> {
     // Find the default audio track
     let track = format
         .tracks()
         .iter()
         .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
         .ok_or(Error::Unsupported("No supported audio tracks found"))?;
-
-    let track_id = track.id;
-    let num_channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(1);
     let sample_rate = track.codec_params.sample_rate.ok_or(Error::Unsupported("Sample rate not found"))?;
 
     // Create a decoder for the track
-    let mut decoder = symphonia::default::get_codecs()
+    let decoder = symphonia::default::get_codecs()
         .make(&track.codec_params, &DecoderOptions::default())?;
+    Ok((track.id, decoder, sample_rate))
+}
 
-    // Decode all packets and collect samples
-    let mut samples = Vec::new();
+/// Linear interpolation between neighbouring samples, good enough for the
+/// odd packet that comes at another rate than the rest of the stream.
+fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if samples.is_empty() || from_rate == to_rate {
+        return samples.to_vec();
+    }
+    let length = (samples.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index.min(samples.len() - 1)];
+            let next = samples[(index + 1).min(samples.len() - 1)];
+            current + (next - current) * fraction
+        })
+        .collect()
+}
 
-    loop {
-        let packet = match format.next_packet() {
-            Ok(packet) => packet,
-            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(err) => return Err(err),
-        };
+impl Iterator for AudioDecoder {
+    type Item = Result<Vec<f32>, Error>;
 
-        // Skip packets that don't belong to our track
-        if packet.track_id() != track_id {
-            continue;
+    /// The next chunk of mono samples. After an error the decoder is done.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.next_chunk();
+        if !matches!(result, Ok(Some(_))) {
+            self.finished = true;
+        }
+        result.transpose()
+    }
+}
+
+impl Drop for AudioDecoder {
+    /// Stops ffmpeg when the decoder is dropped before the end of the file.
+    fn drop(&mut self) {
+        if let DecoderSource::Ffmpeg { child, .. } = &mut self.source {
+            if !self.finished {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
         }
+    }
+}
 
-        // Decode the packet
-        let decoded = decoder.decode(&packet)?;
+/// The ffmpeg invocation that writes `input` to stdout as stereo f32le.
+fn ffmpeg_command(input: &std::ffi::OsStr) -> Command {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-loglevel", "error", "-i"])
+        .arg(input)
+        .args(["-vn", "-ac", "2", "-ar", &FFMPEG_SAMPLE_RATE.to_string(), "-f", "f32le", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
 
-        // Convert to f32 and mix to mono if needed
-        convert_to_mono(&decoded, num_channels, &mut samples);
+/// Adapts a seekable reader to what Symphonia reads from.
+struct SeekableSource<R>(R);
+
+impl<R: Read> Read for SeekableSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
     }
+}
 
-    Ok((samples, sample_rate))
+impl<R: Seek> Seek for SeekableSource<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
 }
 
-fn convert_to_mono(audio_buf: &AudioBufferRef, num_channels: usize, output: &mut Vec<f32>) {
-    match audio_buf {
-        AudioBufferRef::F32(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
+impl<R: Read + Seek + Send + Sync> MediaSource for SeekableSource<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
 
-            if num_channels == 1 {
-                // Mono audio - just copy
-                output.extend_from_slice(planes.planes()[0]);
-            } else {
-                // Multi-channel - average all channels
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i];
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A probe hint from a file extension and a MIME type, such as the
+/// `audio/webm;codecs=opus` a browser's MediaRecorder produces. Either can be
+/// left out; parameters after the MIME type are ignored.
+pub fn format_hint(extension: Option<&str>, mime_type: Option<&str>) -> Hint {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    if let Some(mime_type) = mime_type {
+        let essence = mime_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        if !essence.is_empty() {
+            hint.mime_type(&essence);
         }
-        AudioBufferRef::F64(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| s as f32));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i];
-                    }
-                    output.push((sum / num_channels as f64) as f32);
-                }
+    }
+    hint
+}
+
+/// What [`probe_audio`] tells about a file without decoding it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AudioInfo {
+    /// Short name of the container format, such as `wave`, `isomp4` or `mkv`.
+    pub container: String,
+    /// Short name of the codec of the audio track, when Symphonia knows it.
+    pub codec: Option<String>,
+    /// Whether Symphonia can decode the codec. Anything else needs ffmpeg.
+    pub decodable: bool,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    /// Unknown for streams that don't tell their length up front.
+    pub duration_secs: Option<f64>,
+    pub bits_per_sample: Option<u32>,
+    /// Title, artist and so on, keyed by Symphonia's name for the tag where
+    /// it has one and by the tag's own key otherwise.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Reads the headers of an audio file: only what the container tells up
+/// front, nothing is decoded.
+pub fn probe_audio<P: AsRef<Path>>(path: P) -> Result<AudioInfo, Error> {
+    probe_source(Box::new(File::open(path.as_ref())?))
+}
+
+/// [`probe_audio`] for audio in memory. The start of a file is often
+/// enough, as long as the container keeps its headers there.
+pub fn probe_audio_bytes<B>(bytes: B) -> Result<AudioInfo, Error>
+where
+    B: AsRef<[u8]> + Send + Sync + 'static,
+{
+    probe_source(Box::new(std::io::Cursor::new(bytes)))
+}
+
+fn probe_source(source: Box<dyn MediaSource>) -> Result<AudioInfo, Error> {
+    let mut mss = MediaSourceStream::new(source, Default::default());
+
+    // the loop of `Probe::format`, which doesn't tell what it found
+    let probe = symphonia::default::get_probe();
+    let mut metadata = MetadataLog::default();
+    let (container, mut format) = loop {
+        match probe.next(&mut mss)? {
+            Instantiate::Format(instantiate) => {
+                let container = container_name(instantiate);
+                break (container, instantiate(mss, &FormatOptions::default())?);
             }
-        }
-        AudioBufferRef::S32(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| s as f32 / i32::MAX as f32));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i] as f32 / i32::MAX as f32;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
+            Instantiate::Metadata(reader) => {
+                metadata.push(reader(&MetadataOptions::default()).read_all(&mut mss)?);
             }
         }
-        AudioBufferRef::S24(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-            const S24_MAX: f32 = 8388607.0; // 2^23 - 1
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| s.into_i32() as f32 / S24_MAX));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i].into_i32() as f32 / S24_MAX;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+    };
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(Error::Unsupported("No supported audio tracks found"))?;
+    let params = track.codec_params.clone();
+    let descriptor = symphonia::default::get_codecs().get_codec(params.codec);
+    let codec = match descriptor {
+        Some(descriptor) => Some(descriptor.short_name.to_string()),
+        // the one codec browsers record in that Symphonia has no decoder for
+        None if params.codec == CODEC_TYPE_OPUS => Some("opus".to_string()),
+        None => None,
+    };
+    let duration_secs = match (params.n_frames, params.time_base, params.sample_rate) {
+        (Some(frames), Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
         }
-        AudioBufferRef::S16(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| s as f32 / i16::MAX as f32));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i] as f32 / i16::MAX as f32;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+        (Some(frames), None, Some(sample_rate)) => Some(frames as f64 / sample_rate as f64),
+        _ => None,
+    };
+
+    let mut tags = BTreeMap::new();
+    let revisions = [metadata.metadata().current().cloned(), format.metadata().current().cloned()];
+    for tag in revisions.iter().flatten().flat_map(|revision| revision.tags()) {
+        let key = tag.std_key.map_or_else(|| tag.key.clone(), |key| format!("{:?}", key));
+        tags.insert(key, tag.value.to_string());
+    }
+
+    Ok(AudioInfo {
+        container,
+        codec,
+        decodable: descriptor.is_some(),
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count()),
+        duration_secs,
+        bits_per_sample: params.bits_per_sample,
+        tags,
+    })
+}
+
+type InstantiateFormat = fn(MediaSourceStream, &FormatOptions) -> Result<Box<dyn FormatReader>, Error>;
+
+/// The short name of the format reader Symphonia's default probe made with
+/// `instantiate`.
+fn container_name(instantiate: InstantiateFormat) -> String {
+    use symphonia::core::probe::QueryDescriptor;
+    use symphonia::default::formats::*;
+
+    let descriptors = [
+        AdtsReader::query(),
+        CafReader::query(),
+        FlacReader::query(),
+        IsoMp4Reader::query(),
+        MpaReader::query(),
+        AiffReader::query(),
+        WavReader::query(),
+        OggReader::query(),
+        MkvReader::query(),
+    ];
+    descriptors
+        .iter()
+        .flat_map(|descriptors| descriptors.iter())
+        .find(|descriptor| {
+            matches!(descriptor.inst, Instantiate::Format(other) if std::ptr::fn_addr_eq(other, instantiate))
+        })
+        .map_or_else(|| "unknown".to_string(), |descriptor| descriptor.short_name.to_string())
+}
+
+/// Whether an `ffmpeg` executable can be run, for what Symphonia can't
+/// decode. Looked up once.
+pub fn ffmpeg_available() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffmpeg")
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+/// Decodes a whole file with Symphonia. See [`AudioDecoder`] to decode it
+/// chunk by chunk instead.
+pub fn fetch_audio_data<P: AsRef<Path>>(path: P) -> Result<AudioBuffer, Error> {
+    AudioDecoder::open(path)?.collect_samples()
+}
+
+/// Sample rate ffmpeg resamples to when it does the decoding.
+pub const FFMPEG_SAMPLE_RATE: u32 = 48000;
+
+/// Decodes a file with Symphonia and, for codecs or containers Symphonia
+/// doesn't support (such as opus in webm), pipes it through ffmpeg instead.
+/// Returns mono samples like `fetch_audio_data`.
+pub fn decode_audio_file<P: AsRef<Path>>(path: P) -> Result<AudioBuffer, Error> {
+    AudioDecoder::open_with_fallback(path)?.collect_samples()
+}
+
+/// Decodes encoded audio held in memory like [`decode_audio_file`] decodes a
+/// file.
+pub fn decode_audio_bytes<B>(bytes: B, hint: &Hint) -> Result<AudioBuffer, Error>
+where
+    B: AsRef<[u8]> + Clone + Send + Sync + 'static,
+{
+    AudioDecoder::from_bytes_with_fallback(bytes, hint)?.collect_samples()
+}
+
+/// [`decode_audio_file`] with the report of what decoding had to skip.
+pub fn decode_audio_file_with_report<P: AsRef<Path>>(path: P) -> Result<(AudioBuffer, DecodeReport), Error> {
+    AudioDecoder::open_with_fallback(path)?.collect_with_report()
+}
+
+/// [`fetch_audio_data`] for `duration` of audio from `start` on. A range that
+/// ends past the end of the file gives what there is.
+pub fn fetch_audio_range<P: AsRef<Path>>(
+    path: P,
+    start: Duration,
+    duration: Duration,
+) -> Result<AudioBuffer, Error> {
+    decode_range(AudioDecoder::open(path)?, start, duration)
+}
+
+/// [`decode_audio_file`] for `duration` of audio from `start` on.
+pub fn decode_audio_file_range<P: AsRef<Path>>(
+    path: P,
+    start: Duration,
+    duration: Duration,
+) -> Result<AudioBuffer, Error> {
+    decode_range(AudioDecoder::open_with_fallback(path)?, start, duration)
+}
+
+fn decode_range(mut decoder: AudioDecoder, start: Duration, duration: Duration) -> Result<AudioBuffer, Error> {
+    decoder.seek(start)?;
+    decoder.stop_at(start + duration);
+    decoder.collect_samples()
+}
+
+/// Writes mono samples of any type Symphonia can convert to f32 (such as
+/// `f32` or `f64`) as a 32-bit float WAV.
+pub fn write_wav<P, S>(path: P, samples: &[S], sample_rate: u32) -> hound::Result<()>
+where
+    P: AsRef<Path>,
+    S: IntoSample<f32> + Copy,
+{
+    let mut wav = WavStream::create(path, sample_rate)?;
+    wav.write(samples)?;
+    wav.finish()
+}
+
+/// [`write_wav`] into a writer, such as a `Cursor` over a buffer.
+pub fn write_wav_to<W, S>(writer: W, samples: &[S], sample_rate: u32) -> hound::Result<()>
+where
+    W: Write + Seek,
+    S: IntoSample<f32> + Copy,
+{
+    let mut wav = WavStream::new(writer, sample_rate)?;
+    wav.write(samples)?;
+    wav.finish()
+}
+
+/// A 32-bit float WAV written chunk by chunk, for signals that are never
+/// held in memory at once. Mono unless made [`with_channels`](Self::with_channels),
+/// in which case samples are interleaved.
+pub struct WavStream<W: Write + Seek> {
+    writer: hound::WavWriter<W>,
+}
+
+impl WavStream<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> hound::Result<Self> {
+        // checked before the file is created, so nothing is left behind
+        if sample_rate == 0 {
+            return Err(hound::Error::Unsupported);
         }
-        AudioBufferRef::S8(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| s as f32 / i8::MAX as f32));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += channel[i] as f32 / i8::MAX as f32;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+        WavStream::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavStream<W> {
+    pub fn new(writer: W, sample_rate: u32) -> hound::Result<Self> {
+        WavStream::with_channels(writer, sample_rate, 1)
+    }
+
+    /// Fails with [`hound::Error::Unsupported`] for a zero sample rate or
+    /// channel count, which hound would divide by.
+    pub fn with_channels(writer: W, sample_rate: u32, channels: u16) -> hound::Result<Self> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(hound::Error::Unsupported);
         }
-        AudioBufferRef::U32(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| (s as f32 / u32::MAX as f32) * 2.0 - 1.0));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += (channel[i] as f32 / u32::MAX as f32) * 2.0 - 1.0;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        Ok(WavStream { writer: hound::WavWriter::new(writer, spec)? })
+    }
+
+    pub fn write<S: IntoSample<f32> + Copy>(&mut self, samples: &[S]) -> hound::Result<()> {
+        for sample in samples {
+            let sample: f32 = (*sample).into_sample();
+            self.writer.write_sample(sample)?;
         }
-        AudioBufferRef::U24(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-            const U24_MAX: f32 = 16777215.0; // 2^24 - 1
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| (s.into_u32() as f32 / U24_MAX) * 2.0 - 1.0));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += (channel[i].into_u32() as f32 / U24_MAX) * 2.0 - 1.0;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
-            }
+        Ok(())
+    }
+
+    /// Fills in the header. Dropping the stream instead does the same, but
+    /// without reporting errors.
+    pub fn finish(self) -> hound::Result<()> {
+        self.writer.finalize()
+    }
+}
+
+/// Lets ffmpeg decode the file to raw stereo f32 samples at
+/// [`FFMPEG_SAMPLE_RATE`] and downmixes them to mono as it reads them.
+pub fn decode_with_ffmpeg(path: &Path) -> Result<AudioBuffer, Error> {
+    AudioDecoder::open_with_ffmpeg(path)?.collect_samples()
+}
+
+/// Which part of a multichannel signal is kept when it is made mono.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelSelection {
+    /// The average of all channels.
+    #[default]
+    Mix,
+    Left,
+    Right,
+    /// The average of left and right, leaving out any other channels.
+    Mid,
+    /// Half the difference of left and right: what was panned away from the
+    /// centre. Vocals mixed in the centre mostly cancel out.
+    Side,
+}
+
+impl ChannelSelection {
+    /// The mono sample of one interleaved frame. A mono frame is both left
+    /// and right, so its side is silent.
+    fn sample_of(self, frame: &[f32]) -> f32 {
+        let left = frame[0];
+        let right = frame.get(1).copied().unwrap_or(left);
+        match self {
+            ChannelSelection::Mix => frame.iter().sum::<f32>() / frame.len() as f32,
+            ChannelSelection::Left => left,
+            ChannelSelection::Right => right,
+            ChannelSelection::Mid => (left + right) / 2.0,
+            ChannelSelection::Side => (left - right) / 2.0,
         }
-        AudioBufferRef::U16(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| (s as f32 / u16::MAX as f32) * 2.0 - 1.0));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += (channel[i] as f32 / u16::MAX as f32) * 2.0 - 1.0;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
+    }
+}
+
+/// Makes decoded buffers of any sample format and channel count mono, as f32
+/// samples in [-1, 1]. Symphonia's sample conversion does the scaling, so
+/// signed and unsigned formats end up centred the same way. The conversion
+/// buffer is kept between calls.
+pub struct Downmixer {
+    selection: ChannelSelection,
+    buffer: Option<SampleBuffer<f32>>,
+}
+
+impl Downmixer {
+    pub fn new(selection: ChannelSelection) -> Downmixer {
+        Downmixer { selection, buffer: None }
+    }
+
+    pub fn selection(&self) -> ChannelSelection {
+        self.selection
+    }
+
+    /// Appends the mono samples of `audio_buf` to `output`. The channel count
+    /// is the buffer's own, which can change from one packet to the next.
+    pub fn downmix(&mut self, audio_buf: &AudioBufferRef, output: &mut Vec<f32>) {
+        let spec = *audio_buf.spec();
+        let channels = spec.channels.count();
+        let needed = audio_buf.frames() * channels;
+        if needed == 0 {
+            return;
+        }
+        if self.buffer.as_ref().is_none_or(|buffer| buffer.capacity() < needed) {
+            let frames = audio_buf.capacity().max(audio_buf.frames());
+            self.buffer = Some(SampleBuffer::new(frames as u64, spec));
+        }
+        let buffer = self.buffer.as_mut().expect("the buffer was just made");
+        buffer.copy_interleaved_ref(audio_buf.clone());
+        output.extend(buffer.samples().chunks_exact(channels).map(|frame| self.selection.sample_of(frame)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer as PlanarBuffer, Channels, Signal, SignalSpec};
+    use symphonia::core::sample::{i24, u24, Sample};
+
+    #[test]
+    fn decoder_streams_the_same_samples_fetch_audio_data_returns() {
+        let path = std::env::temp_dir().join(format!("main_app_decoder_{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..22050 * 3 {
+            let value = ((i as f32 * 0.05).sin() * 10000.0) as i16;
+            writer.write_sample(value).unwrap();
+            writer.write_sample(value / 2).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let decoder = AudioDecoder::open(&path).unwrap();
+        assert_eq!(decoder.sample_rate(), 22050);
+        let chunks: Vec<Vec<f32>> = decoder.collect::<Result<_, _>>().unwrap();
+        assert!(chunks.len() > 1);
+
+        let AudioBuffer { samples, sample_rate } = fetch_audio_data(&path).unwrap();
+        assert_eq!(sample_rate, 22050);
+        assert_eq!(samples.len(), 22050 * 3);
+        assert_eq!(chunks.concat(), samples);
+        // both channels are averaged, scaled the way Symphonia converts i16
+        let expected = ((100.0f32 * 0.05).sin() * 10000.0) as i16;
+        assert!((samples[100] - (expected as f32 + (expected / 2) as f32) / 2.0 / 32768.0).abs() < 1e-6);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn wav_bytes(sample_rate: u32, seconds: usize) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for i in 0..sample_rate as usize * seconds {
+            writer.write_sample(((i as f32 * 0.02).sin() * 8000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        wav.into_inner()
+    }
+
+    #[test]
+    fn audio_is_decoded_from_bytes_readers_and_streams() {
+        let wav = wav_bytes(16000, 2);
+        let hint = format_hint(None, Some("audio/wav; codecs=1"));
+
+        let AudioBuffer { samples: from_bytes, sample_rate } = decode_audio_bytes(wav.clone(), &hint).unwrap();
+        assert_eq!(sample_rate, 16000);
+        assert_eq!(from_bytes.len(), 32000);
+
+        let from_reader = AudioDecoder::from_reader(std::io::Cursor::new(wav.clone()), &hint)
+            .unwrap()
+            .collect_samples()
+            .unwrap();
+        assert_eq!(from_reader.samples, from_bytes);
+
+        // a reader that can only go forward, like a pipe
+        struct Forward(std::io::Cursor<Vec<u8>>);
+        impl Read for Forward {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(777);
+                self.0.read(&mut buf[..len])
             }
         }
-        AudioBufferRef::U8(buf) => {
-            let planes = buf.planes();
-            let num_frames = buf.frames();
-
-            if num_channels == 1 {
-                output.extend(planes.planes()[0].iter().map(|&s| (s as f32 / u8::MAX as f32) * 2.0 - 1.0));
-            } else {
-                for i in 0..num_frames {
-                    let mut sum = 0.0;
-                    for channel in planes.planes() {
-                        sum += (channel[i] as f32 / u8::MAX as f32) * 2.0 - 1.0;
-                    }
-                    output.push(sum / num_channels as f32);
-                }
+        let from_stream = AudioDecoder::from_stream(Forward(std::io::Cursor::new(wav)), &format_hint(Some("wav"), None))
+            .unwrap()
+            .collect_samples()
+            .unwrap();
+        assert_eq!(from_stream.samples, from_bytes);
+
+        assert!(AudioDecoder::from_bytes(b"not audio at all".to_vec(), &hint).is_err());
+    }
+
+    /// An IMA ADPCM WAV of `blocks` blocks, two to a packet, where the listed
+    /// blocks have a step index the decoder refuses.
+    fn adpcm_wav(blocks: usize, corrupt: &[usize]) -> Vec<u8> {
+        const BLOCK_ALIGN: u16 = 256;
+        const FRAMES_PER_BLOCK: u16 = 505;
+        let mut data = Vec::new();
+        for block in 0..blocks {
+            data.extend_from_slice(&0i16.to_le_bytes());
+            data.push(if corrupt.contains(&block) { 200 } else { 20 });
+            data.push(0);
+            data.extend((0..BLOCK_ALIGN - 4).map(|i| (i % 7 + 1) as u8 * 0x11));
+        }
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 28 + 12 + 8 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&20u32.to_le_bytes());
+        wav.extend_from_slice(&0x11u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&(8000 * BLOCK_ALIGN as u32 / FRAMES_PER_BLOCK as u32).to_le_bytes());
+        wav.extend_from_slice(&BLOCK_ALIGN.to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&FRAMES_PER_BLOCK.to_le_bytes());
+        wav.extend_from_slice(b"fact");
+        wav.extend_from_slice(&4u32.to_le_bytes());
+        wav.extend_from_slice(&(blocks as u32 * FRAMES_PER_BLOCK as u32).to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[test]
+    fn corrupt_packets_are_dropped_and_counted() {
+        let hint = format_hint(Some("wav"), None);
+        let (AudioBuffer { samples: clean, sample_rate }, report) = AudioDecoder::from_bytes(adpcm_wav(40, &[]), &hint)
+            .unwrap()
+            .collect_with_report()
+            .unwrap();
+        assert_eq!(sample_rate, 8000);
+        assert_eq!(clean.len(), 40 * 505);
+        assert_eq!(report.packets_decoded, 20);
+        assert_eq!(report.packets_dropped, 0);
+
+        // block 10 spoils the packet it shares with block 11
+        let (AudioBuffer { samples, .. }, report) = AudioDecoder::from_bytes(adpcm_wav(40, &[10]), &hint)
+            .unwrap()
+            .collect_with_report()
+            .unwrap();
+        assert_eq!(report.packets_dropped, 1);
+        assert_eq!(report.packets_decoded, 19);
+        assert_eq!(report.frames, 38 * 505);
+        assert_eq!(samples.len(), 38 * 505);
+        assert_eq!(&samples[..10 * 505], &clean[..10 * 505]);
+        assert_eq!(&samples[10 * 505..], &clean[12 * 505..]);
+        assert!((report.duration().as_secs_f64() - 38.0 * 505.0 / 8000.0).abs() < 1e-6);
+
+        // nothing but corrupt packets is not audio
+        let every_block: Vec<usize> = (0..400).collect();
+        let result = AudioDecoder::from_bytes(adpcm_wav(400, &every_block), &hint).unwrap().collect_with_report();
+        assert!(matches!(result, Err(Error::DecodeError(_))));
+    }
+
+    #[test]
+    fn packets_at_another_rate_are_resampled() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let halved = resample_linear(&samples, 16000, 8000);
+        assert_eq!(halved.len(), 50);
+        assert_eq!(halved[10], 20.0);
+        let doubled = resample_linear(&samples, 8000, 16000);
+        assert_eq!(doubled.len(), 200);
+        assert_eq!(doubled[21], 10.5);
+    }
+
+    fn downmixed<S: Sample>(channels: &[S], selection: ChannelSelection) -> f32
+    where
+        PlanarBuffer<S>: AsAudioBufferRef,
+    {
+        let layout = [Channels::FRONT_LEFT, Channels::FRONT_RIGHT, Channels::FRONT_CENTRE];
+        let spec = SignalSpec::new(44100, layout[..channels.len()].iter().fold(Channels::empty(), |all, &one| all | one));
+        let mut buffer = PlanarBuffer::<S>::new(4, spec);
+        buffer.render_reserved(Some(4));
+        for (channel, &sample) in channels.iter().enumerate() {
+            buffer.chan_mut(channel).fill(sample);
+        }
+        let mut output = Vec::new();
+        Downmixer::new(selection).downmix(&buffer.as_audio_buffer_ref(), &mut output);
+        assert_eq!(output.len(), 4);
+        output[0]
+    }
+
+    #[test]
+    fn every_sample_format_is_downmixed_the_same() {
+        fn check<S: Sample>(left: S, right: S)
+        where
+            PlanarBuffer<S>: AsAudioBufferRef,
+        {
+            // left is at half of full scale, right at a quarter below zero
+            let expected = [
+                (ChannelSelection::Mix, 0.125),
+                (ChannelSelection::Left, 0.5),
+                (ChannelSelection::Right, -0.25),
+                (ChannelSelection::Mid, 0.125),
+                (ChannelSelection::Side, 0.375),
+            ];
+            for (selection, expected) in expected {
+                let sample = downmixed(&[left, right], selection);
+                assert!((sample - expected).abs() < 1e-6, "{} {:?}: {}", type_name::<S>(), selection, sample);
             }
         }
+
+        check(192u8, 96u8);
+        check(49152u16, 24576u16);
+        check(u24(12582912), u24(6291456));
+        check(3221225472u32, 1610612736u32);
+        check(64i8, -32i8);
+        check(16384i16, -8192i16);
+        check(i24(4194304), i24(-2097152));
+        check(1073741824i32, -536870912i32);
+        check(0.5f32, -0.25f32);
+        check(0.5f64, -0.25f64);
+    }
+
+    #[test]
+    fn mono_and_surround_buffers_use_their_own_channel_count() {
+        assert_eq!(downmixed(&[0.5f32], ChannelSelection::Mix), 0.5);
+        assert_eq!(downmixed(&[0.5f32], ChannelSelection::Right), 0.5);
+        assert_eq!(downmixed(&[0.5f32], ChannelSelection::Side), 0.0);
+        // mix averages every channel, mid only left and right
+        assert_eq!(downmixed(&[0.5f32, 0.25, 0.75], ChannelSelection::Mix), 0.5);
+        assert_eq!(downmixed(&[0.5f32, 0.25, 0.75], ChannelSelection::Mid), 0.375);
+    }
+
+    fn range_of(mut decoder: AudioDecoder, start: f64, end: f64) -> Vec<f32> {
+        decoder.seek(Duration::from_secs_f64(start)).unwrap();
+        decoder.stop_at(Duration::from_secs_f64(end));
+        decoder.collect_samples().unwrap().samples
     }
-}
 
+    #[test]
+    fn a_time_range_decodes_the_same_samples_seekable_or_not() {
+        let wav = wav_bytes(16000, 3);
+        let hint = format_hint(Some("wav"), None);
+        let all = decode_audio_bytes(wav.clone(), &hint).unwrap().samples;
+
+        // packets hold 1152 samples, so the seek lands before sample 20000
+        // and the samples up to it are decoded and dropped
+        let seeked = range_of(AudioDecoder::from_bytes(wav.clone(), &hint).unwrap(), 1.25, 1.75);
+        assert_eq!(seeked, all[20000..28000]);
+        let stream = AudioDecoder::from_stream(std::io::Cursor::new(wav.clone()), &hint).unwrap();
+        let skipped = range_of(stream, 1.25, 1.75);
+        assert_eq!(skipped, all[20000..28000]);
+
+        // ranges past the end give what there is
+        assert_eq!(range_of(AudioDecoder::from_bytes(wav.clone(), &hint).unwrap(), 2.5, 4.0), all[40000..]);
+        assert!(range_of(AudioDecoder::from_bytes(wav.clone(), &hint).unwrap(), 5.0, 6.0).is_empty());
+
+        // a stream can't go back
+        let mut stream = AudioDecoder::from_stream(std::io::Cursor::new(wav), &hint).unwrap();
+        stream.next().unwrap().unwrap();
+        assert!(stream.seek(Duration::ZERO).is_err());
+    }
 
+    #[test]
+    fn probing_reads_the_headers_only() {
+        let wav = wav_bytes(16000, 2);
+        let info = probe_audio_bytes(wav.clone()).unwrap();
+        assert_eq!(info.container, "wave");
+        assert_eq!(info.codec.as_deref(), Some("pcm_s16le"));
+        assert!(info.decodable);
+        assert_eq!(info.sample_rate, Some(16000));
+        assert_eq!(info.channels, Some(1));
+        assert_eq!(info.duration_secs, Some(2.0));
+        assert_eq!(info.bits_per_sample, Some(16));
+
+        // the start of the file tells as much
+        assert_eq!(probe_audio_bytes(wav[..1000].to_vec()).unwrap(), info);
+        assert!(probe_audio_bytes(b"not audio at all".to_vec()).is_err());
+    }
+
+    #[test]
+    fn wav_written_from_f64_reads_back_as_the_same_samples() {
+        let samples: Vec<f64> = (0..1000).map(|i| (i as f64 * 0.01).sin()).collect();
+        let mut wav = std::io::Cursor::new(Vec::new());
+        write_wav_to(&mut wav, &samples, 11025).unwrap();
+
+        let AudioBuffer { samples: decoded, sample_rate } = decode_audio_bytes(wav.into_inner(), &Hint::new()).unwrap();
+        assert_eq!(sample_rate, 11025);
+        assert_eq!(decoded, samples.iter().map(|value| *value as f32).collect::<Vec<f32>>());
+    }
+
+    #[test]
+    fn wav_with_a_zero_sample_rate_is_refused() {
+        let mut wav = std::io::Cursor::new(Vec::new());
+        assert!(matches!(write_wav_to(&mut wav, &[0.0f32; 10], 0), Err(hound::Error::Unsupported)));
+    }
+}
@@ -1,6 +1,8 @@
 use std::any::type_name;
+use std::collections::HashMap;
 
-use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use serde_json::{json, Value};
 
@@ -10,33 +12,52 @@ use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
 use cot::http::method::Method;
+use cot::http::header::{HeaderValue, CONTENT_TYPE};
 use cot::form::{
     Form,
     FormResult
 };
 use cot::request::{Request, RequestExt};
-use cot::request::extractors::RequestDb;
+use cot::request::extractors::{Path, RequestDb, UrlQuery};
+use cot::auth::Auth;
+use cot::session::Session;
 use cot::db::{
     query,
+    Auto,
+    Database,
     Model
 };
+use cot::db::query::{Expr, Query};
+use cot::error::NotFound;
 use cot::response::{Response, ResponseExt};
 use cot::json::Json;
 use cot::html::Html;
 use cot::Body;
 use cot::bytes::Buf;
 
-use num_complex::Complex;
-
 use askama::Template;
 use symphonia::core::errors::Error;
 
-use main_app::player::play_audio;
+use main_app::player::{clip, play_audio, render_to_wav};
 use crate::my_random::random_string;
-use crate::download_helpers::download_youtube_audio;
+use crate::download_helpers::{
+    canonical_youtube_playlist_url,
+    canonical_youtube_url,
+    YtDlpDownloader
+};
+use crate::ingest::SUPPORTED_EXTENSIONS;
+use main_app::utils::{decode_audio_bytes, decode_audio_file_range, ffmpeg_available, format_hint, probe_audio, probe_audio_bytes, AudioBuffer, AudioInfo};
+use crate::storage::TempDir;
+use crate::debug_audio::AudioDump;
+use crate::audio_store::{AudioStore, StoreError};
 use crate::models::{
+    IngestJob,
+    JobState,
+    IngestSegment,
+    PlaylistIngest,
     Song,
-    FingerPrint
+    FingerPrint,
+    SearchLog
 };
 use crate::shazam::MatchError;
 use crate::shazam::Match;
@@ -45,14 +66,436 @@ use crate::shazam::Match;
 fn print_type_of<T>(value: &T){
     println!("{}", type_name::<T>());
 }
+
+/// The error of a view whose job, playlist or song doesn't exist.
+fn not_found()->cot::Error{
+    cot::Error::from(NotFound::new())
+}
+const RECENT_SONGS_LIMIT: u64 = 10;
+const RECENT_SEARCHES_LIMIT: u64 = 5;
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    song_count: u64,
+    fingerprint_count: u64,
+    search_count: u64,
+    recent_songs: Vec<Song>,
+    recent_searches: Vec<SearchLog>
+}
+
+/// Fetches up to `limit` rows of a table of `total` rows, newest first,
+/// after skipping the `offset` newest.
+///
+/// The query builder has no ORDER BY and the database returns rows in no
+/// particular order, so the rows are sorted by id here. Ids are unique and
+/// positive, so at most `total - offset - limit` rows have an id up to that
+/// number and the filter always keeps the ones wanted.
+async fn newest<T: Model>(
+    db: &Database,
+    total: u64,
+    offset: u64,
+    limit: u64,
+    id: impl Fn(&T)->i64
+)->cot::db::Result<Vec<T>>{
+    let wanted = offset.saturating_add(limit);
+    let below = total.saturating_sub(wanted);
+    let mut rows = Query::<T>::new()
+        .filter(Expr::gt(Expr::field("id"), Expr::value(below as i64)))
+        .all(db)
+        .await?;
+    rows.sort_by_key(|row| std::cmp::Reverse(id(row)));
+    Ok(rows.into_iter().skip(offset as usize).take(limit as usize).collect())
+}
+
+pub async fn index_view(RequestDb(db): RequestDb) -> cot::Result<Html> {
+    let song_count = Song::objects().count(&db).await?;
+    let fingerprint_count = FingerPrint::objects().count(&db).await?;
+
+    let recent_songs = newest(&db, song_count, 0, RECENT_SONGS_LIMIT, |song: &Song| song.id.unwrap()).await?;
+
+    let search_count = SearchLog::objects().count(&db).await?;
+    let recent_searches = newest(&db, search_count, 0, RECENT_SEARCHES_LIMIT, |log: &SearchLog| log.id.unwrap()).await?;
+
+    let template = IndexTemplate{
+        song_count,
+        fingerprint_count,
+        search_count,
+        recent_songs,
+        recent_searches
+    };
+    Ok(Html::new(template.render()?))
+}
+
 #[derive(Template)]
 #[template(path = "upload.html")]
 struct UploadTemplate {
     youtube_url: String,
+    tracklist: String,
     errors: Vec<String>,
     success: String
 }
 
+impl UploadTemplate {
+    fn max_file_size_mb(&self)->u64{
+        crate::settings::get().upload.max_file_size_mb
+    }
+
+    fn upload_extensions(&self)->String{
+        SUPPORTED_EXTENSIONS.iter().map(|ext| format!(".{}", ext)).collect::<Vec<_>>().join(",")
+    }
+}
+
+fn is_multipart(request: &Request)->bool{
+    request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"))
+}
+
+/// Streams the `audio_file` field of a multipart upload to a temporary
+/// directory of its own and queues an ingest job for it. Errors are messages for the upload page.
+async fn queue_uploaded_file(request: Request, db: &Arc<Database>)->Result<i64, String>{
+    let boundary = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| multer::parse_boundary(content_type).ok())
+        .ok_or("The upload is not a valid multipart form.".to_string())?;
+
+    let max_file_size_mb = crate::settings::get().upload.max_file_size_mb;
+    let constraints = multer::Constraints::new()
+        .allowed_fields(vec!["audio_file", "tracklist"])
+        .size_limit(multer::SizeLimit::new().per_field(max_file_size_mb * 1024 * 1024));
+    let mut multipart = multer::Multipart::with_constraints(
+        request.into_body().into_data_stream(),
+        boundary,
+        constraints
+    );
+
+    // the track list may come before or after the file
+    let mut upload = None;
+    let mut tracklist = None;
+    loop{
+        let field = match multipart.next_field().await{
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return Err(upload_error_message(err, max_file_size_mb))
+        };
+        if field.name() == Some("tracklist"){
+            let text = field.text().await.map_err(|err| upload_error_message(err, max_file_size_mb))?;
+            tracklist = checked_tracklist(&text)?;
+        }
+        else if upload.is_none(){
+            upload = Some(save_uploaded_field(field, max_file_size_mb).await?);
+        }
+    }
+    let Some((upload_dir, file_name, file_path)) = upload else {
+        return Err("Choose an audio file to upload.".to_string());
+    };
+    let path = file_path.clone();
+    let probed = tokio::task::spawn_blocking(move || probe_audio(&path))
+        .await
+        .map_err(|_| "failed to read the uploaded file. try again later".to_string())?;
+    if let Some(reason) = unsupported_audio_reason(&probed, true){
+        return Err(format!("{}: {}", file_name, reason));
+    }
+
+    let mut job = IngestJob::new_local_file(&file_name, &file_path.to_string_lossy());
+    job.tracklist = tracklist;
+    if let Err(err) = job.save(db).await{
+        eprintln!("failed to queue ingest job: {}", err);
+        return Err("failed to queue the file. try again later".to_string());
+    }
+    upload_dir.keep();
+    let job_id = job.id.unwrap();
+    crate::jobs::enqueue(job_id);
+    Ok(job_id)
+}
+
+/// Streams an uploaded audio file to a temporary directory of its own. The
+/// directory is removed again on any error, the job takes it over once queued.
+async fn save_uploaded_field(field: multer::Field<'_>, max_file_size_mb: u64)->Result<(TempDir, String, std::path::PathBuf), String>{
+    let file_name = match field.file_name(){
+        Some(file_name) if !file_name.is_empty() => file_name.to_string(),
+        _ => return Err("Choose an audio file to upload.".to_string())
+    };
+    let extension = std::path::Path::new(&file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .filter(|ext| SUPPORTED_EXTENSIONS.contains(&ext.as_str()))
+        .ok_or(format!(
+            "{} is not a supported audio file. Upload one of: {}.",
+            file_name,
+            SUPPORTED_EXTENSIONS.join(", ")
+        ))?;
+
+    let upload_dir = TempDir::create("upload").map_err(|err| {
+        eprintln!("failed to create an upload directory: {}", err);
+        "failed to save the uploaded file. try again later".to_string()
+    })?;
+    let file_path = upload_dir.path().join(format!("upload.{}", extension));
+    save_upload(field, &file_path, max_file_size_mb).await?;
+    Ok((upload_dir, file_name, file_path))
+}
+
+/// How much of a file the upload page sends to [`probe_upload_view`].
+const PROBE_UPLOAD_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Why probed audio can't be ingested, if it can't. `complete` tells whether
+/// the whole file was probed or only its start, which can end before the
+/// headers do.
+fn unsupported_audio_reason(probed: &Result<AudioInfo, Error>, complete: bool)->Option<String>{
+    match probed{
+        Ok(info) if info.decodable || ffmpeg_available() => None,
+        Ok(info) => Some(format!(
+            "{} audio can't be decoded on this server.",
+            info.codec.as_deref().unwrap_or("This")
+        )),
+        Err(Error::IoError(err)) if !complete && err.kind() == std::io::ErrorKind::UnexpectedEof => None,
+        Err(Error::Unsupported(_)) => Some("This is not audio in a supported format.".to_string()),
+        Err(err) => Some(format!("The file could not be read ({}).", err))
+    }
+}
+
+/// Tells what an audio file is from its first bytes, so the upload page can
+/// turn down files that would only fail once queued.
+pub async fn probe_upload_view(request: Request)->cot::Result<Json<Value>>{
+    let bytes = match read_probe_upload(request).await{
+        Ok(bytes) => bytes,
+        Err(error) => return Ok(Json(json!({"info": null, "error": error})))
+    };
+    let probed = tokio::task::spawn_blocking(move || probe_audio_bytes(bytes))
+        .await
+        .map_err(cot::Error::internal)?;
+    let error = unsupported_audio_reason(&probed, false);
+    Ok(Json(json!({"info": probed.ok(), "error": error})))
+}
+
+async fn read_probe_upload(request: Request)->Result<cot::bytes::Bytes, String>{
+    let boundary = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| multer::parse_boundary(content_type).ok())
+        .ok_or("The upload is not a valid multipart form.".to_string())?;
+    let constraints = multer::Constraints::new()
+        .allowed_fields(vec!["audio_file"])
+        .size_limit(multer::SizeLimit::new().per_field(PROBE_UPLOAD_BYTES));
+    let mut multipart = multer::Multipart::with_constraints(
+        request.into_body().into_data_stream(),
+        boundary,
+        constraints
+    );
+    let max_mb = PROBE_UPLOAD_BYTES / 1024 / 1024;
+    match multipart.next_field().await{
+        Ok(Some(field)) => field.bytes().await.map_err(|err| upload_error_message(err, max_mb)),
+        Ok(None) => Err("Choose an audio file to upload.".to_string()),
+        Err(err) => Err(upload_error_message(err, max_mb))
+    }
+}
+
+/// A pasted track list, checked before the job is queued. Blank means the
+/// source is one song.
+fn checked_tracklist(text: &str)->Result<Option<String>, String>{
+    if text.trim().is_empty(){
+        return Ok(None);
+    }
+    crate::segments::parse_tracklist(text)?;
+    Ok(Some(text.trim().to_string()))
+}
+
+/// The chapters of a video, written as a track list for the job.
+async fn chapter_tracklist(youtube_url: &str)->Result<String, String>{
+    let chapters = YtDlpDownloader::from_settings(&crate::settings::get().downloader).chapters(youtube_url).await.map_err(|err| {
+        eprintln!("failed to read the chapters of {}: {}", youtube_url, err);
+        "failed to read the chapters of the video. try again later".to_string()
+    })?;
+    if chapters.is_empty(){
+        return Err("The video has no chapters.".to_string());
+    }
+    Ok(crate::segments::format_tracklist(&chapters))
+}
+
+/// The track list a YouTube upload asked for: the pasted one, or else the
+/// chapters of the video when splitting by chapters is ticked.
+async fn requested_tracklist(form: &crate::forms::MusicUploadForm, youtube_url: &str)->Result<Option<String>, String>{
+    if let Some(tracklist) = checked_tracklist(form.tracklist.as_deref().unwrap_or(""))?{
+        return Ok(Some(tracklist));
+    }
+    if form.split_chapters.unwrap_or(false){
+        return chapter_tracklist(youtube_url).await.map(Some);
+    }
+    Ok(None)
+}
+
+fn job_status_url(job_id: i64)->String{
+    format!("/upload/jobs/{}/", job_id)
+}
+
+fn playlist_status_url(playlist_id: i64)->String{
+    format!("/upload/playlists/{}/", playlist_id)
+}
+
+/// Lists the playlist with yt-dlp and queues its videos. Errors are messages
+/// for the upload page.
+async fn queue_playlist(db: &Database, playlist_url: &str)->Result<i64, String>{
+    let playlist = YtDlpDownloader::from_settings(&crate::settings::get().downloader).list_playlist(playlist_url).await.map_err(|err| {
+        eprintln!("failed to list {}: {}", playlist_url, err);
+        "failed to read the playlist. try again later".to_string()
+    })?;
+    if playlist.video_urls.is_empty(){
+        return Err("The playlist has no videos.".to_string());
+    }
+
+    match crate::jobs::queue_playlist(db, playlist_url, &playlist).await{
+        Ok(playlist_ingest) => Ok(playlist_ingest.id.unwrap()),
+        Err(err) => {
+            eprintln!("failed to queue playlist {}: {}", playlist_url, err);
+            Err("failed to queue the playlist. try again later".to_string())
+        }
+    }
+}
+
+/// The unfinished job already ingesting this video, if any.
+async fn pending_youtube_job(db: &Database, youtube_url: &str)->cot::db::Result<Option<i64>>{
+    let jobs = query!(IngestJob, $youtube_url == youtube_url.to_string()).all(db).await?;
+    Ok(jobs
+        .into_iter()
+        .find(|job| !job.job_state().is_finished())
+        .map(|job| job.id.unwrap()))
+}
+
+#[derive(Template)]
+#[template(path = "ingest_job.html")]
+struct IngestJobTemplate {
+    job: IngestJob,
+    segments: Vec<IngestSegment>
+}
+
+pub async fn ingest_job_view(
+    Path(job_id): Path<i64>,
+    RequestDb(db): RequestDb
+)->cot::Result<Html>
+{
+    let job = crate::jobs::get_job(&db, job_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let segments = crate::jobs::segments_of(&db, job_id).await?;
+    let template = IngestJobTemplate{
+        job,
+        segments
+    };
+    Ok(Html::new(template.render()?))
+}
+
+#[derive(Template)]
+#[template(path = "playlist_ingest.html")]
+struct PlaylistIngestTemplate {
+    playlist: PlaylistIngest,
+    jobs: Vec<IngestJob>,
+    finished: usize
+}
+
+pub async fn playlist_ingest_view(
+    Path(playlist_id): Path<i64>,
+    RequestDb(db): RequestDb
+)->cot::Result<Html>
+{
+    let playlist = crate::jobs::get_playlist(&db, playlist_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let jobs = crate::jobs::playlist_jobs(&db, playlist_id).await?;
+    let template = PlaylistIngestTemplate{
+        finished: jobs.iter().filter(|job| job.job_state().is_finished()).count(),
+        playlist,
+        jobs
+    };
+    Ok(Html::new(template.render()?))
+}
+
+pub async fn playlist_ingest_json_view(
+    Path(playlist_id): Path<i64>,
+    RequestDb(db): RequestDb
+)->cot::Result<Json<Value>>
+{
+    let playlist = crate::jobs::get_playlist(&db, playlist_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let jobs = crate::jobs::playlist_jobs(&db, playlist_id).await?;
+    let count = |state: JobState| jobs.iter().filter(|job| job.job_state() == state).count();
+
+    Ok(Json(json!({
+        "playlist": playlist,
+        "done": count(JobState::Done),
+        "failed": count(JobState::Failed),
+        "skipped": count(JobState::Skipped),
+        "pending": jobs.iter().filter(|job| !job.job_state().is_finished()).count(),
+        "jobs": jobs
+    })))
+}
+
+pub async fn ingest_job_json_view(
+    Path(job_id): Path<i64>,
+    RequestDb(db): RequestDb
+)->cot::Result<Json<IngestJob>>
+{
+    let job = crate::jobs::get_job(&db, job_id)
+        .await?
+        .ok_or_else(not_found)?;
+    Ok(Json(job))
+}
+
+/// Cancels a job from its status page and goes back to it.
+pub async fn cancel_ingest_job_view(
+    Path(job_id): Path<i64>,
+    RequestDb(db): RequestDb,
+    request: Request
+)->cot::Result<Response>
+{
+    let mut job = crate::jobs::get_job(&db, job_id)
+        .await?
+        .ok_or_else(not_found)?;
+    if request.method() == Method::POST{
+        crate::jobs::cancel(&db, &mut job).await;
+    }
+    Ok(Response::new_redirect(job_status_url(job_id)))
+}
+
+async fn save_upload(mut field: multer::Field<'_>, path: &std::path::Path, max_file_size_mb: u64)->Result<(), String>{
+    let write_failed = |err: std::io::Error| {
+        eprintln!("failed to write upload to {}: {}", path.display(), err);
+        "failed to save the uploaded file. try again later".to_string()
+    };
+
+    if let Some(directory) = path.parent(){
+        tokio::fs::create_dir_all(directory).await.map_err(write_failed)?;
+    }
+    let mut file = File::create(path).await.map_err(write_failed)?;
+    loop{
+        match field.chunk().await{
+            Ok(Some(chunk)) => file.write_all(&chunk).await.map_err(write_failed)?,
+            Ok(None) => break,
+            Err(err) => return Err(upload_error_message(err, max_file_size_mb))
+        }
+    }
+    file.flush().await.map_err(write_failed)
+}
+
+fn upload_error_message(err: multer::Error, max_file_size_mb: u64)->String{
+    match err{
+        multer::Error::FieldSizeExceeded{..} | multer::Error::StreamSizeExceeded{..} =>
+            format!("The file is larger than the {} MB upload limit.", max_file_size_mb),
+        multer::Error::UnknownField{..} => "The upload form contains unexpected fields.".to_string(),
+        err => {
+            eprintln!("failed to read upload: {}", err);
+            "The upload was interrupted. try again".to_string()
+        }
+    }
+}
+
 union number_32{
     as_u32: u32,
     as_f32: f32,
@@ -60,6 +503,20 @@ union number_32{
 }
 
 pub async fn upload_view(mut request: Request, RequestDb(mut db): RequestDb)->Response{
+    if request.method() == Method::POST && is_multipart(&request){
+        let template = match queue_uploaded_file(request, &db).await{
+            Ok(job_id) => return Response::new_redirect(job_status_url(job_id)),
+            Err(error) => UploadTemplate{
+                youtube_url: "".to_string(),
+                tracklist: "".to_string(),
+                errors: vec![error],
+                success: "".to_string()
+            }
+        };
+        return Response::new(
+            Body::fixed(template.render().unwrap())
+        );
+    }
     if request.method() == Method::POST{
         let form_result = crate::forms::MusicUploadForm::from_request(&mut request).await.unwrap();
         println!("DATABASE TYPE");
@@ -68,94 +525,107 @@ pub async fn upload_view(mut request: Request, RequestDb(mut db): RequestDb)->Re
             FormResult::Ok(form) => {
 
                 println!("youtube url: {}", form.youtube_url);
-                let file_path = format!("output/{}.mp3", random_string(5 as usize));
 
-                if query!(Song, $youtube_url==form.youtube_url.clone()).all(&db).await.unwrap().len()>0{
+                let wants_tracks = form.split_chapters.unwrap_or(false)
+                    || form.tracklist.as_deref().is_some_and(|tracklist| !tracklist.trim().is_empty());
+                if let Some(playlist_url) = canonical_youtube_playlist_url(&form.youtube_url){
+                    if wants_tracks{
+                        let template = UploadTemplate{
+                            tracklist: form.tracklist.clone().unwrap_or_default(),
+                            youtube_url:form.youtube_url,
+                            errors: vec!["Track lists only work with a single video.".to_string()],
+                            success: "".to_string()
+                        };
+                        return Response::new(
+                            Body::fixed(template.render().unwrap())
+                        );
+                    }
+                    return match queue_playlist(&db, &playlist_url).await{
+                        Ok(playlist_id) => Response::new_redirect(playlist_status_url(playlist_id)),
+                        Err(error) => {
+                            let template = UploadTemplate{
+                                tracklist: form.tracklist.clone().unwrap_or_default(),
+                                youtube_url:form.youtube_url,
+                                errors: vec![error],
+                                success: "".to_string()
+                            };
+                            Response::new(
+                                Body::fixed(template.render().unwrap())
+                            )
+                        }
+                    };
+                }
+
+                let Some(youtube_url) = canonical_youtube_url(&form.youtube_url) else {
                     let template = UploadTemplate{
+                        tracklist: form.tracklist.clone().unwrap_or_default(),
                         youtube_url:form.youtube_url,
-                        errors: vec!["The video is already uploaded.".to_string()],
+                        errors: vec!["This is not a link to a YouTube video or playlist.".to_string()],
                         success: "".to_string()
                     };
                     return Response::new(
                         Body::fixed(template.render().unwrap())
                     );
-                }
+                };
 
-                let res = download_youtube_audio(&form.youtube_url[..], &file_path[..]).await;
-                if res != Ok(()){
+                // songs stored before canonicalisation keep the URL as it was typed
+                if query!(Song, $youtube_url==youtube_url.clone()).exists(&db).await.unwrap()
+                    || query!(Song, $youtube_url==form.youtube_url.clone()).exists(&db).await.unwrap(){
                     let template = UploadTemplate{
+                        tracklist: form.tracklist.clone().unwrap_or_default(),
                         youtube_url:form.youtube_url,
-                        errors: vec!["failed to download the video. try again later".to_string()],
+                        errors: vec!["The video is already uploaded.".to_string()],
                         success: "".to_string()
                     };
                     return Response::new(
                         Body::fixed(template.render().unwrap())
-                    );               
+                    );
                 }
 
-                let audio_data:Result<(Vec<f32>, u32), _> = main_app::utils::fetch_audio_data(&file_path);
+                // the video may still be on its way into the catalogue
+                match pending_youtube_job(&db, &youtube_url).await{
+                    Ok(Some(job_id)) => return Response::new_redirect(job_status_url(job_id)),
+                    Ok(None) => {},
+                    Err(err) => eprintln!("failed to look up pending jobs: {}", err)
+                }
 
-                let mut spectrogram_option:Option<(Vec<Vec<Complex<f64>>>, f64)> = match audio_data{
-                    Ok((audio_samples, sample_rate)) => {
-                        let audio_duration = (audio_samples.len() as f64) / sample_rate as f64;
-                        let spectogram_result = crate::shazam::spectogram::spectrogram(
-                            &audio_samples.into_iter().map(|value| value as f64).collect::<Vec<f64>>()[..],
-                            sample_rate as usize
+                let tracklist = match requested_tracklist(&form, &youtube_url).await{
+                    Ok(tracklist) => tracklist,
+                    Err(error) => {
+                        let template = UploadTemplate{
+                            tracklist: form.tracklist.clone().unwrap_or_default(),
+                            youtube_url:form.youtube_url,
+                            errors: vec![error],
+                            success: "".to_string()
+                        };
+                        return Response::new(
+                            Body::fixed(template.render().unwrap())
                         );
-                        if let Ok(spectrogram) = spectogram_result{
-                            Some((spectrogram, audio_duration))
-                        }
-                        else {
-                            None
-                        }
-                    },
-                    Err(_) => None
+                    }
                 };
-                std::fs::remove_file(&file_path[..]);
 
-                if  spectrogram_option == None{                    
+                let mut job = IngestJob::new_youtube(&youtube_url);
+                job.tracklist = tracklist;
+                if let Err(err) = job.save(&db).await{
+                    eprintln!("failed to queue ingest job: {}", err);
                     let template = UploadTemplate{
+                        tracklist: form.tracklist.clone().unwrap_or_default(),
                         youtube_url:form.youtube_url,
-                        errors: vec![],
-                        success: "failed to save audio".to_string()
+                        errors: vec!["failed to queue the video. try again later".to_string()],
+                        success: "".to_string()
                     };
                     return Response::new(
                         Body::fixed(template.render().unwrap())
                     );
                 }
-
-
-                let (spectrogram, audio_duration) = spectrogram_option.unwrap();
-                let peaks = crate::shazam::spectogram::extract_peaks(&spectrogram, audio_duration);
-
-                let mut song = Song::new(&form.youtube_url);
-                song.save(&db).await;
-                let song_id :i64 = song.id.unwrap();
-                let fingerprints = crate::shazam::fingerprint::fingerprint(peaks, song_id);
-
-                println!("SAVING FINGERPRINTS...");
-                for (address, couple) in fingerprints{
-                    let mut fingerprint = FingerPrint::new(
-                        address,
-                        couple.anchor_time_ms,
-                        couple.song_id
-                    );
-                    fingerprint.save(&db).await;
-                }
-                println!("DONE DB upDATE..");
-
-                let template = UploadTemplate{
-                    youtube_url:form.youtube_url,
-                    errors: vec![],
-                    success: "".to_string()
-                };
-                Response::new(
-                    Body::fixed(template.render().unwrap())
-                ) 
+                let job_id = job.id.unwrap();
+                crate::jobs::enqueue(job_id);
+                Response::new_redirect(job_status_url(job_id))
             }
             _ =>{
                 let template = UploadTemplate{
                     youtube_url:"".to_string(),
+                    tracklist: "".to_string(),
                     errors: vec![],
                     success: "".to_string()
                 };
@@ -168,6 +638,7 @@ pub async fn upload_view(mut request: Request, RequestDb(mut db): RequestDb)->Re
     else{
         let template = UploadTemplate{
             youtube_url:"".to_string(),
+            tracklist: "".to_string(),
             errors: vec![],
             success: "".to_string()
         };
@@ -182,40 +653,83 @@ pub async fn upload_view(mut request: Request, RequestDb(mut db): RequestDb)->Re
 struct SearchTemplate {
     error: String,
     success: String,
-    results: Vec<String>
+    results: Vec<SearchResult>
+}
+
+/// A matched song as listed on the search page. Local files have no URL
+/// and are listed by file name only.
+struct SearchResult {
+    title: String,
+    youtube_url: String,
+    /// Where [`song_preview_view`] plays the matched part of the song, empty
+    /// when its audio isn't kept.
+    preview_url: String
+}
+
+impl SearchResult {
+    fn from_url(youtube_url: &str)->SearchResult{
+        SearchResult{
+            title: youtube_url.to_string(),
+            youtube_url: youtube_url.to_string(),
+            preview_url: String::new()
+        }
+    }
 }
 
 
 pub async fn search_view(
     mut request: Request,
+    session: Session,
+    auth: Auth,
     RequestDb(mut db): RequestDb
 )->Response
 {
-    if let Some((audio_sample, sample_rate)) = get_request_audio_data(request).await{
-        let duration: f64 = audio_sample.len() as f64 / sample_rate as f64;
-        let spectogram = crate::shazam::spectogram::spectrogram(
-            &audio_sample.iter().map(|x| *x as f64).collect::<Vec<f64>>()[..],
-            sample_rate as usize
-        );
+    let user_id = auth.user().id().and_then(|id| id.as_int());
+    let session_id = search_session_id(&session).await;
 
-        let matches = crate::shazam::find_matches(
-            &db,
-            &audio_sample.iter().map(|x| *x as f64).collect::<Vec<f64>>()[..],
-            duration,
-            sample_rate as usize
-        ).await;
+    let audio_sample = match get_request_audio_data(request).await{
+        Ok(audio_sample) => audio_sample,
+        Err(error) => {
+            let template = SearchTemplate{
+                error,
+                success: "".to_string(),
+                results: vec![]
+            };
+            return Response::new(
+                Body::fixed(template.render().unwrap())
+            );
+        }
+    };
+    if let Some(audio_sample) = audio_sample{
+        let start_time = Instant::now();
+        let matches = crate::shazam::find_matches(&db, &audio_sample).await;
 
         let search_results = Vec::<String>::new();
 
         match matches{
-            Ok((found_songs, duration)) => {
-                print_type_of(&found_songs);
+            Ok((found_songs, hash_count, query_duration)) => {
+                let mut search_log = SearchLog::new(user_id, &session_id, audio_sample.len() as u32, audio_sample.sample_rate);
+                search_log.query_duration_ms = query_duration.as_millis() as u32;
+                search_log.hash_count = hash_count as u32;
+                search_log.top_matches = top_matches_json(&found_songs).to_string();
+                search_log.chosen_song_id = found_songs.first().map(|mtch| mtch.song_id);
+                log_search(&db, search_log, &audio_sample).await;
+
                 // println!("TUMI HAKIS FASDF SAFS: {:?}", found_songs);
-                let mut songs: Vec<String> = Vec::new();
+                let mut songs: Vec<SearchResult> = Vec::new();
                 for mtch in found_songs{
                     // println!("match>>>>>");
                     let song_match: Match = mtch;
-                    songs.push(song_match.youtube_url);
+                    let preview_url = if song_match.has_audio{
+                        format!("/songs/{}/preview/?offset_ms={}", song_match.song_id, song_match.offset_ms)
+                    } else {
+                        String::new()
+                    };
+                    songs.push(SearchResult{
+                        title: song_match.title,
+                        youtube_url: song_match.youtube_url,
+                        preview_url
+                    });
                 }
 
                 let template = SearchTemplate{
@@ -226,11 +740,17 @@ pub async fn search_view(
                     Body::fixed(template.render().unwrap())
                 );
             },
-            _ => {
+            Err(err) => {
+                eprintln!("search failed: {}", err);
+                // failed searches are logged too, so their samples can be replayed
+                let mut search_log = SearchLog::new(user_id, &session_id, audio_sample.len() as u32, audio_sample.sample_rate);
+                search_log.query_duration_ms = start_time.elapsed().as_millis() as u32;
+                log_search(&db, search_log, &audio_sample).await;
+
                 let template = SearchTemplate{
                     error: "some error occured".to_string(),
                     success: "".to_string(),
-                    results: vec!{"".to_string()}
+                    results: vec!{SearchResult::from_url("")}
                 };
                 return Response::new(
                     Body::fixed(template.render().unwrap())
@@ -248,7 +768,7 @@ pub async fn search_view(
     let template = SearchTemplate{
         error: "".to_string(),
         success: "".to_string(),
-        results: vec!{"https://www.youtube.com/watch?v=TH6OzKUB9Sg".to_string()}
+        results: vec!{SearchResult::from_url("https://www.youtube.com/watch?v=TH6OzKUB9Sg")}
     };
     Response::new(
         Body::fixed(template.render().unwrap())
@@ -256,9 +776,245 @@ pub async fn search_view(
 }
 
 
+const SEARCH_LOG_TOP_MATCHES: usize = 5;
+const SEARCH_HISTORY_PAGE_SIZE: u64 = 50;
+const SEARCH_SESSION_KEY: &str = "search_session_id";
+
+/// Returns an id that stays stable for the visitor across searches, stored in their session.
+async fn search_session_id(session: &Session)->String{
+    if let Ok(Some(session_id)) = session.get::<String>(SEARCH_SESSION_KEY).await{
+        return session_id;
+    }
+    let session_id = random_string(16);
+    if let Err(err) = session.insert(SEARCH_SESSION_KEY, session_id.clone()).await{
+        eprintln!("failed to store search session id: {}", err);
+    }
+    session_id
+}
+
+/// Saves the log of a search along with its sample, when the query store
+/// keeps them, and its debug dump.
+async fn log_search(db: &Database, mut search_log: SearchLog, audio_sample: &AudioBuffer){
+    if let Err(err) = search_log.save(db).await{
+        eprintln!("failed to save search log: {}", err);
+        return;
+    }
+    store_query_sample(db, &mut search_log, audio_sample).await;
+    if let Some(dump) = AudioDump::for_search(search_log.id.unwrap()){
+        dump.write_query(audio_sample);
+    }
+}
+
+/// Keeps the query audio next to its search log when the query store is enabled.
+async fn store_query_sample(
+    db: &Database,
+    search_log: &mut SearchLog,
+    audio_sample: &AudioBuffer
+){
+    let query_store = &crate::settings::get().query_store;
+    if !query_store.enabled{
+        return;
+    }
+
+    match crate::query_store::store_query_sample(query_store, search_log.id.unwrap(), audio_sample){
+        Ok(path) => {
+            search_log.sample_path = Some(path.to_string_lossy().into_owned());
+            if let Err(err) = search_log.update(db).await{
+                eprintln!("failed to link query sample to search log: {}", err);
+            }
+        },
+        Err(err) => eprintln!("failed to store query sample: {}", err)
+    }
+}
+
+/// Preview clips start this long before the requested offset, so the part
+/// that was matched isn't cut into.
+const PREVIEW_LEAD_MS: u64 = 2_000;
+const PREVIEW_DEFAULT_MS: u64 = 10_000;
+const PREVIEW_MAX_MS: u64 = 30_000;
+
+/// A WAV clip of a song's own audio around `offset_ms`, such as where a
+/// search matched it, `duration_ms` long. Rendered offline, so it works on
+/// servers without an audio device.
+pub async fn song_preview_view(
+    Path(song_id): Path<i64>,
+    UrlQuery(params): UrlQuery<HashMap<String, String>>,
+    RequestDb(db): RequestDb
+)->cot::Result<Response>
+{
+    let song = query!(Song, $id == Auto::from(song_id))
+        .get(&db)
+        .await?
+        .ok_or_else(not_found)?;
+    let param_ms = |name: &str| params.get(name).and_then(|value| value.parse::<u64>().ok());
+    let offset_ms = param_ms("offset_ms").unwrap_or(0);
+    let length = Duration::from_millis(param_ms("duration_ms").unwrap_or(PREVIEW_DEFAULT_MS).clamp(1, PREVIEW_MAX_MS));
+    let start = Duration::from_millis(offset_ms.saturating_sub(PREVIEW_LEAD_MS));
+
+    let wav = tokio::task::spawn_blocking(move || render_preview(&song, start, length))
+        .await
+        .map_err(cot::Error::internal)?
+        .map_err(cot::Error::internal)?
+        .ok_or_else(not_found)?;
+    let mut response = Response::new(Body::fixed(wav));
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("audio/wav"));
+    Ok(response)
+}
+
+/// Cuts the clip from the song's file when it is still there, at full
+/// quality, or else from the analysis signal in the audio store. `None` when
+/// neither is at hand.
+fn render_preview(song: &Song, start: Duration, length: Duration)->Result<Option<Vec<u8>>, String>{
+    let source = match (&song.file_path, &song.audio_hash){
+        (Some(file_path), _) if std::path::Path::new(file_path).is_file() => {
+            let audio = decode_audio_file_range(file_path, start, length).map_err(|err| err.to_string())?;
+            clip(&audio, Duration::ZERO, length)
+        },
+        (_, Some(audio_hash)) => {
+            let Some(store) = AudioStore::from_settings(&crate::settings::get().audio_store) else {
+                return Ok(None);
+            };
+            match store.get(audio_hash){
+                Ok(signal) => clip(&signal, start, length),
+                Err(StoreError::NotFound(_)) => return Ok(None),
+                Err(err) => return Err(err.to_string())
+            }
+        },
+        _ => return Ok(None)
+    };
+    render_to_wav(source).map(Some).map_err(|err| err.to_string())
+}
+
+fn top_matches_json(matches: &[Match])->Value{
+    Value::Array(
+        matches
+            .iter()
+            .take(SEARCH_LOG_TOP_MATCHES)
+            .map(|mtch| json!({
+                "song_id": mtch.song_id,
+                "youtube_url": mtch.youtube_url,
+                "title": mtch.title,
+                "score": mtch.score,
+                "offset_ms": mtch.offset_ms
+            }))
+            .collect()
+    )
+}
+
+/// Fetches one page of search logs, newest first.
+async fn search_log_page(db: &Database, page: u64)->cot::db::Result<(Vec<SearchLog>, u64)>{
+    let total = SearchLog::objects().count(db).await?;
+
+    let offset = page.saturating_mul(SEARCH_HISTORY_PAGE_SIZE);
+    if offset >= total{
+        return Ok((vec![], total));
+    }
+
+    let logs = newest(db, total, offset, SEARCH_HISTORY_PAGE_SIZE, |log: &SearchLog| log.id.unwrap()).await?;
+    Ok((logs, total))
+}
+
+/// The `?page=` of a history request. Pages are capped far past any real
+/// history, so the page arithmetic can't overflow.
+fn requested_page(params: &HashMap<String, String>)->u64{
+    params
+        .get("page")
+        .and_then(|page| page.parse::<u64>().ok())
+        .unwrap_or(0)
+        .min(u32::MAX as u64)
+}
+
+#[derive(Template)]
+#[template(path = "search_history.html")]
+struct SearchHistoryTemplate {
+    logs: Vec<SearchLogRow>,
+    total: u64,
+    page: u64,
+    has_next: bool
+}
+
+struct SearchLogRow {
+    log: SearchLog,
+    chosen_url: String,
+    chosen_title: String,
+    match_count: usize
+}
+
+pub async fn search_history_view(
+    UrlQuery(params): UrlQuery<HashMap<String, String>>,
+    RequestDb(db): RequestDb
+)->cot::Result<Html>
+{
+    let page = requested_page(&params);
+    let (logs, total) = search_log_page(&db, page).await?;
+
+    let logs = logs
+        .into_iter()
+        .map(|log| {
+            let top_matches: Vec<Value> = serde_json::from_str(&log.top_matches).unwrap_or_default();
+            let chosen = top_matches.first();
+            let chosen_url = chosen
+                .and_then(|mtch| mtch["youtube_url"].as_str())
+                .unwrap_or("")
+                .to_string();
+            // logs written before local files were supported have no title
+            let chosen_title = chosen
+                .and_then(|mtch| mtch["title"].as_str())
+                .unwrap_or(&chosen_url)
+                .to_string();
+            SearchLogRow{
+                match_count: top_matches.len(),
+                chosen_url,
+                chosen_title,
+                log
+            }
+        })
+        .collect();
+
+    let template = SearchHistoryTemplate{
+        logs,
+        total,
+        page,
+        has_next: page.saturating_add(1).saturating_mul(SEARCH_HISTORY_PAGE_SIZE) < total
+    };
+    Ok(Html::new(template.render()?))
+}
+
+pub async fn search_history_json_view(
+    UrlQuery(params): UrlQuery<HashMap<String, String>>,
+    RequestDb(db): RequestDb
+)->cot::Result<Json<Value>>
+{
+    let page = requested_page(&params);
+    let (logs, total) = search_log_page(&db, page).await?;
+
+    Ok(Json(json!({
+        "total": total,
+        "page": page,
+        "page_size": SEARCH_HISTORY_PAGE_SIZE,
+        "logs": logs
+    })))
+}
+
+
+/// Whether an uploaded search sample is an encoded recording rather than the
+/// raw f32 samples older clients send.
+fn is_encoded_upload(content_type: Option<&str>, file_name: &str)->bool{
+    let is_media_type = content_type.is_some_and(|content_type| {
+        content_type.starts_with("audio/") || content_type.starts_with("video/")
+    });
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    is_media_type || extension.is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.as_str()) || ext == "mp4")
+}
+
+/// The search sample of a request, `None` when there is none. An encoded
+/// recording that can't be decoded is an error with the message to show.
 pub async fn get_request_audio_data(
     request: Request,
-) -> Option<(Vec<f32>, u32)> {
+) -> Result<Option<AudioBuffer>, String> {
     
     if request.method() == Method::POST {
         // Get the Content-Type header and clone the boundary
@@ -276,7 +1032,7 @@ pub async fn get_request_audio_data(
         let boundary = match boundary {
             Some(b) => b,
             None => {
-                return None;
+                return Ok(None);
             }
         };
         
@@ -298,6 +1054,7 @@ pub async fn get_request_audio_data(
         // Process each field
         let mut sample_rate: Option<u32> = None;
         let mut audio_samples: Option<Vec<f32>> = None;
+        let mut encoded_audio = None;
 
         while let Some(mut field) = multipart.next_field().await.unwrap() {
             let field_name = field.name().unwrap_or("unknown").to_string();
@@ -307,7 +1064,15 @@ pub async fn get_request_audio_data(
                 sample_rate = Some(field.text().await.unwrap().parse().unwrap());
             }
             else if let Some(original_filename) = file_name {                
+                let content_type = field.content_type().map(|mime| mime.to_string());
                 let data = field.bytes().await.unwrap();
+                // recordings come in the container the browser recorded them
+                // in, raw f32 samples as plain binary
+                if is_encoded_upload(content_type.as_deref(), &original_filename){
+                    let extension = std::path::Path::new(&original_filename).extension().and_then(|ext| ext.to_str());
+                    encoded_audio = Some((data, format_hint(extension, content_type.as_deref())));
+                    continue;
+                }
                 let mut audio_samples_local = Vec::<f32>::new();
                 let mut shifter=0;
                 let mut cur_val=0_u32;
@@ -332,14 +1097,27 @@ pub async fn get_request_audio_data(
                 audio_samples = Some(audio_samples_local);
             }
         }
+        if let Some((data, hint)) = encoded_audio{
+            let decoded = tokio::task::spawn_blocking(move || decode_audio_bytes(data, &hint))
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|decoded| decoded.map_err(|err| err.to_string()));
+            return match decoded{
+                Ok(audio) => Ok(Some(audio)),
+                Err(err) => {
+                    eprintln!("failed to decode the search sample: {}", err);
+                    Err("could not decode the recording".to_string())
+                }
+            };
+        }
         if sample_rate == None || audio_samples == None{
-            return None;
+            return Ok(None);
         }
         // println!("SAMPLES>>: {:?}", audio_samples);
         // println!("samples: {}", audio_samples.clone().unwrap()[2000]);
         // println!("samples: {}", audio_samples.clone().unwrap()[2355]);
         // println!("samples: {}", audio_samples.clone().unwrap()[2388]);
-        return Some((audio_samples.unwrap(), sample_rate.unwrap()));
+        return Ok(Some(AudioBuffer::new(audio_samples.unwrap(), sample_rate.unwrap())));
     }
-    return None;
+    return Ok(None);
 }